@@ -0,0 +1,94 @@
+// Times the engine on a config with many chords, to check the time per event doesn't grow with
+// the number of maps. The crate has no library, so the modules the engine needs are built into
+// the bench as they are into the binary.
+#![allow(dead_code, unused_imports)]
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+#[path = "../src/auxiliary/mod.rs"]
+mod auxiliary;
+#[path = "../src/clock.rs"]
+mod clock;
+#[path = "../src/config/mod.rs"]
+mod config;
+#[path = "../src/device/mod.rs"]
+mod device;
+#[path = "../src/engine/mod.rs"]
+mod engine;
+#[path = "../src/errors.rs"]
+mod errors;
+#[path = "../src/key.rs"]
+mod key;
+#[path = "../src/logging.rs"]
+mod logging;
+#[path = "../src/mapping.rs"]
+mod mapping;
+#[path = "../src/position.rs"]
+mod position;
+#[path = "../src/privileges.rs"]
+mod privileges;
+#[path = "../src/trace.rs"]
+mod trace;
+
+pub use crate::key::Key;
+
+use crate::config::parsing::parse_config;
+use crate::engine::Engine;
+use crate::key::{KeyEvent, KeyState};
+
+/// The first keys, which all have names to write the chords with.
+const KEYS: u16 = 60;
+
+/// An engine with `count` chords of three keys, so that every key typed is part of some chord.
+fn engine_with_chords(count: usize) -> Engine {
+    let keys: Vec<Key> = (1..=KEYS).map(Key::new).collect();
+    let mut config = String::from("[mappings.maps]\n");
+    let mut chords = 0;
+    'chords: for (i, a) in keys.iter().enumerate() {
+        for (j, b) in keys.iter().enumerate().skip(i + 1) {
+            for c in keys.iter().skip(j + 1) {
+                if chords == count {
+                    break 'chords;
+                }
+                config.push_str(&format!("\"{:?}+{:?}+{:?}\" = \"a\"\n", a, b, c));
+                chords += 1;
+            }
+        }
+    }
+    Engine::new(&parse_config(&config).unwrap())
+}
+
+/// Presses and releases of keys spread over all the chords.
+fn events() -> Vec<KeyEvent> {
+    (0..2_000u64)
+        .map(|i| {
+            let key = Key::new(1 + (i / 2 * 7 % KEYS as u64) as u16);
+            match i % 2 {
+                0 => KeyEvent::new(key, KeyState::Pressed, Duration::from_millis(i * 100)),
+                _ => KeyEvent::new(key, KeyState::Released, Duration::from_millis(i * 100 + 10)),
+            }
+        })
+        .collect()
+}
+
+fn per_event(c: &mut Criterion) {
+    let events = events();
+    let mut group = c.benchmark_group("events through the engine");
+    for count in [100, 1_000, 30_000] {
+        let mut engine = engine_with_chords(count);
+        group.bench_function(BenchmarkId::from_parameter(count), |bencher| {
+            bencher.iter(|| {
+                // Timers which expired before each event are handled as it's processed.
+                for event in &events {
+                    engine.process(*event);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, per_event);
+criterion_main!(benches);
@@ -1,11 +1,22 @@
-use crate::device::DeviceInfo;
-use crate::Key;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::config::schema::{DeviceMatcher, DeviceProperties, DevicesConfig};
+use crate::device::{DeviceClass, DeviceInfo};
 
 pub trait FilterableDevices<T> {
     fn extract_keyboards(self) -> Option<T>;
-    fn extract_named_devices(self, names: &Vec<String>) -> Option<T>;
-    fn remove_named_devices(self, names: &Vec<String>) -> Option<T>;
+    fn extract_devices_of_classes(self, classes: &[DeviceClass]) -> Option<T>;
+    #[allow(dead_code)]
+    fn extract_named_devices(self, names: &[String]) -> Option<T>;
+    #[allow(dead_code)]
+    fn remove_named_devices(self, names: &[String]) -> Option<T>;
+    fn extract_matching_devices(self, matchers: &[DeviceMatcher]) -> Option<T>;
+    fn remove_matching_devices(self, matchers: &[DeviceMatcher]) -> Option<T>;
     fn extract_devices_whose_name_doesnt_contain(self, substring: &str) -> Option<T>;
+    /// The devices `config` selects, which `DevicesConfig::selection` explains one at a time.
+    fn extract_selected_devices(self, config: &DevicesConfig) -> Option<T>;
 }
 
 impl<T> FilterableDevices<Vec<T>> for Vec<T>
@@ -23,7 +34,18 @@ where
         }
     }
 
-    fn extract_named_devices(self, names: &Vec<String>) -> Option<Vec<T>> {
+    fn extract_devices_of_classes(self, classes: &[DeviceClass]) -> Option<Vec<T>> {
+        let devices: Vec<T> = self
+            .into_iter()
+            .filter(|device| classes.contains(&device.class()))
+            .collect();
+        match devices.len() {
+            0 => None,
+            _ => Some(devices),
+        }
+    }
+
+    fn extract_named_devices(self, names: &[String]) -> Option<Vec<T>> {
         let devices: Vec<T> = self
             .into_iter()
             .filter(|device| match device.name() {
@@ -37,7 +59,7 @@ where
         }
     }
 
-    fn remove_named_devices(self, names: &Vec<String>) -> Option<Vec<T>> {
+    fn remove_named_devices(self, names: &[String]) -> Option<Vec<T>> {
         let devices: Vec<T> = self
             .into_iter()
             .filter(|device| match device.name() {
@@ -51,6 +73,28 @@ where
         }
     }
 
+    fn extract_matching_devices(self, matchers: &[DeviceMatcher]) -> Option<Vec<T>> {
+        let devices: Vec<T> = self
+            .into_iter()
+            .filter(|device| matchers.iter().any(|matcher| matcher.matches(device)))
+            .collect();
+        match devices.len() {
+            0 => None,
+            _ => Some(devices),
+        }
+    }
+
+    fn remove_matching_devices(self, matchers: &[DeviceMatcher]) -> Option<Vec<T>> {
+        let devices: Vec<T> = self
+            .into_iter()
+            .filter(|device| !matchers.iter().any(|matcher| matcher.matches(device)))
+            .collect();
+        match devices.len() {
+            0 => None,
+            _ => Some(devices),
+        }
+    }
+
     fn extract_devices_whose_name_doesnt_contain(self, substring: &str) -> Option<Vec<T>> {
         let devices: Vec<T> = self
             .into_iter()
@@ -61,11 +105,188 @@ where
             _ => Some(devices),
         }
     }
+
+    fn extract_selected_devices(self, config: &DevicesConfig) -> Option<Vec<T>> {
+        let included = match (&config.include, &config.classes) {
+            (Some(include), _) => self.extract_matching_devices(include),
+            (None, None) => self
+                .extract_devices_whose_name_doesnt_contain("virtual")?
+                .extract_keyboards(),
+            (None, Some(classes)) => self
+                .extract_devices_whose_name_doesnt_contain("virtual")?
+                .extract_devices_of_classes(classes),
+        }?;
+        match &config.exclude {
+            Some(exclude) => included.remove_matching_devices(exclude),
+            None => Some(included),
+        }
+    }
 }
 
 fn is_keyboard<T: DeviceInfo>(device: &T) -> bool {
-    return device.supported_keys().map_or(false, |mut keys| {
-        // TODO: Currently just patched this with call to evdev, but need to wrap key types in this project's Key struct
-        keys.any(|key| key == Key::KEY_ENTER)
-    });
+    device.class() == DeviceClass::Keyboard
+}
+
+impl DeviceMatcher {
+    pub fn matches<T: DeviceInfo>(&self, device: &T) -> bool {
+        match self {
+            DeviceMatcher::Name(name) => device.name() == Some(name.as_str()),
+            DeviceMatcher::Properties(properties) => properties.matches(device),
+        }
+    }
+}
+
+impl DeviceProperties {
+    /// Whether every property given matches `device`.
+    pub fn matches<T: DeviceInfo>(&self, device: &T) -> bool {
+        fn check<V>(expected: &Option<V>, matches: impl FnOnce(&V) -> bool) -> bool {
+            expected.as_ref().is_none_or(matches)
+        }
+        let name = device.name();
+        check(&self.name, |expected| name == Some(expected.as_str()))
+            && check(&self.name_glob, |glob| {
+                name.is_some_and(|name| glob_matches(glob, name))
+            })
+            && check(&self.name_regex, |regex| {
+                name.is_some_and(|name| regex.regex().is_ok_and(|regex| regex.is_match(name)))
+            })
+            && check(&self.id, |id| {
+                parse_device_id(id).is_some_and(|id| device.id() == Some(id))
+            })
+            && check(&self.phys, |phys| device.phys() == Some(phys.as_str()))
+            && check(&self.uniq, |uniq| device.uniq() == Some(uniq.as_str()))
+            && check(&self.class, |class| device.class() == *class)
+            && check(&self.path, |path| {
+                device
+                    .path()
+                    .is_some_and(|device_path| same_file(path, device_path))
+            })
+    }
+
+    /// Check the properties can be matched, describing the problem if not.
+    pub fn check(&self) -> Result<(), String> {
+        if self == &DeviceProperties::default() {
+            return Err("A device table needs at least one property to match".to_owned());
+        }
+        if let Some(regex) = &self.name_regex {
+            regex
+                .regex()
+                .map_err(|err| format!("Invalid name_regex {:?}: {}", regex.as_str(), err))?;
+        }
+        if let Some(id) = &self.id {
+            parse_device_id(id).ok_or_else(|| {
+                format!(
+                    "Invalid device id {:?}, expected vendor and product IDs in hex, e.g. \"046d:c52b\"",
+                    id
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DeviceMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let properties = match self {
+            DeviceMatcher::Name(name) => return write!(f, "{}", name),
+            DeviceMatcher::Properties(properties) => properties,
+        };
+        let path = properties
+            .path
+            .as_ref()
+            .map(|path| path.display().to_string());
+        let name_regex = properties
+            .name_regex
+            .as_ref()
+            .map(|regex| regex.as_str().to_owned());
+        let class = properties.class.map(|class| class.to_string());
+        let fields = [
+            ("name", &properties.name),
+            ("name_glob", &properties.name_glob),
+            ("name_regex", &name_regex),
+            ("id", &properties.id),
+            ("phys", &properties.phys),
+            ("uniq", &properties.uniq),
+            ("path", &path),
+            ("class", &class),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .filter_map(|(field, value)| Some(format!("{} = {:?}", field, value.as_ref()?)))
+            .collect();
+        write!(f, "{{{}}}", fields.join(", "))
+    }
+}
+
+/// Vendor and product IDs written in hex, e.g. "046d:c52b".
+fn parse_device_id(id: &str) -> Option<(u16, u16)> {
+    let (vendor, product) = id.split_once(':')?;
+    Some((
+        u16::from_str_radix(vendor, 16).ok()?,
+        u16::from_str_radix(product, 16).ok()?,
+    ))
+}
+
+/// Whether `text` matches `glob`, where "*" matches any text and "?" any one character.
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // Where the last "*" was, and the text it matched up to, to backtrack to.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut g, mut t) = (0, 0);
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_g, star_t)) => {
+                    star = Some((star_g, star_t + 1));
+                    g = star_g + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+/// Whether `a` and `b` are the same file, following links such as those in /dev/input/by-id.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod test_glob_matches {
+    use super::glob_matches;
+
+    #[test]
+    fn stars_match_any_text() {
+        assert!(glob_matches("Logitech*", "Logitech USB Receiver"));
+        assert!(glob_matches("*USB*", "Logitech USB Receiver"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("*Keyboard", "Logitech USB Receiver"));
+    }
+
+    #[test]
+    fn question_marks_match_one_character() {
+        assert!(glob_matches("event?", "event3"));
+        assert!(!glob_matches("event?", "event12"));
+    }
+
+    #[test]
+    fn other_characters_match_exactly() {
+        assert!(glob_matches("abc", "abc"));
+        assert!(!glob_matches("abc", "abcd"));
+        assert!(!glob_matches("abcd", "abc"));
+    }
 }
@@ -0,0 +1,76 @@
+// Time as seen by the remapper. Every timing decision is made against the times of the key
+// events, which come from the kernel, so the clock is only needed to know how much time has
+// passed when waiting for a timer with no events arriving. It must be the same clock the kernel
+// timestamps events with.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use nix::time::{clock_gettime, ClockId};
+
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+/// CLOCK_MONOTONIC, which devices are set to timestamp their events with, as unlike the
+/// default CLOCK_REALTIME it never jumps.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Duration {
+        clock_gettime(ClockId::CLOCK_MONOTONIC)
+            .map(Duration::from)
+            .unwrap_or_default()
+    }
+}
+
+/// A clock which only moves when told to, for tests and scripted events. Its clones share the
+/// same time, so a scripted source can move the engine's clock along with its events.
+#[derive(Debug, Default, Clone)]
+pub struct VirtualClock {
+    now: Rc<Cell<Duration>>,
+}
+
+impl VirtualClock {
+    /// Move the clock forward to `time`, if it's later than now.
+    pub fn advance_to(&self, time: Duration) {
+        self.now.set(self.now.get().max(time));
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+#[cfg(test)]
+mod test_clock {
+    use super::*;
+
+    #[test]
+    fn monotonic_clock_does_not_go_backwards() {
+        let clock = MonotonicClock;
+        let before = clock.now();
+        assert!(clock.now() >= before);
+        assert!(before > Duration::ZERO);
+    }
+
+    #[test]
+    fn virtual_clock_only_moves_forward() {
+        let clock = VirtualClock::default();
+        clock.advance_to(Duration::from_millis(20));
+        clock.advance_to(Duration::from_millis(10));
+        assert_eq!(clock.now(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn virtual_clock_clones_share_the_time() {
+        let clock = VirtualClock::default();
+        let clone = clock.clone();
+        clone.advance_to(Duration::from_millis(20));
+        assert_eq!(clock.now(), Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,42 @@
+use std::path::Path;
+
+use crate::config::compact::format_map;
+use crate::config::ergonomics::check_chords;
+use crate::config::parsing::read_config_file;
+use crate::errors::Error;
+
+/// Check the config can be read, and score how comfortable each of its chords is to play.
+pub fn check_config(path: &Path) -> Result<(), Error> {
+    let config = read_config_file(path)?;
+    let maps = config
+        .mappings
+        .and_then(|mappings| mappings.maps)
+        .unwrap_or_default();
+    println!("Config {:?} is valid, with {} maps.", path, maps.len());
+
+    if config.position.is_empty() {
+        println!("Add a [position] table to check how comfortable chords are to play.");
+        return Ok(());
+    }
+
+    let reports = check_chords(&maps, &config.position);
+    println!("Chord scores, out of 100:");
+    for report in &reports {
+        println!("{:>5}  {}", report.score, format_map(report.map));
+        for issue in &report.issues {
+            println!("         {}", issue);
+        }
+    }
+
+    let impossible = reports
+        .iter()
+        .filter(|report| report.is_impossible())
+        .count();
+    match impossible {
+        0 => Ok(()),
+        _ => Err(Error::Message(format!(
+            "{} chords are impossible to play",
+            impossible
+        ))),
+    }
+}
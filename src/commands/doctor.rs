@@ -0,0 +1,338 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::{access, getgroups, getuid, AccessFlags, Group, User};
+
+use crate::errors::Error;
+
+const UINPUT_PATH: &str = "/dev/uinput";
+const UDEV_RULE_PATH: &str = "/etc/udev/rules.d/99-chorded-key-remapper.rules";
+
+/// Whether the user is in a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Membership {
+    /// The group doesn't exist.
+    NoGroup,
+    NotMember,
+    /// The user was added to the group, but it only applies to new logins.
+    AfterRelogin,
+    Member,
+}
+
+/// What matters about the system for the remapper to run without root.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    pub user: String,
+    pub is_root: bool,
+    /// Each /dev/input/event* device, and whether it can be read.
+    pub event_devices: Vec<(PathBuf, bool)>,
+    /// Whether /dev/uinput can be written, if it exists.
+    pub uinput_writable: Option<bool>,
+    pub uinput_module_loaded: bool,
+    pub input_group: Membership,
+    pub uinput_group: Membership,
+}
+
+/// A change to the system, in the order they need making.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Fix {
+    LoadUinputModule,
+    CreateGroup(&'static str),
+    JoinGroup(&'static str),
+    InstallUdevRule,
+    Relogin,
+}
+
+/// The outcome of checking one thing.
+#[derive(Debug)]
+pub struct Check {
+    pub description: String,
+    pub passed: bool,
+    pub fixes: Vec<Fix>,
+}
+
+impl Environment {
+    pub fn detect() -> Environment {
+        let user = User::from_uid(getuid()).ok().flatten();
+        let groups = getgroups().unwrap_or_default();
+        let membership = |name: &str| match (Group::from_name(name), &user) {
+            (Ok(Some(group)), Some(user))
+                if groups.contains(&group.gid) || user.gid == group.gid =>
+            {
+                Membership::Member
+            }
+            (Ok(Some(group)), Some(user)) if group.mem.contains(&user.name) => {
+                Membership::AfterRelogin
+            }
+            (Ok(Some(_)), _) => Membership::NotMember,
+            _ => Membership::NoGroup,
+        };
+
+        let mut event_devices: Vec<(PathBuf, bool)> = fs::read_dir("/dev/input")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("event"))
+            })
+            .map(|path| {
+                let readable = access(&path, AccessFlags::R_OK).is_ok();
+                (path, readable)
+            })
+            .collect();
+        event_devices.sort();
+
+        let uinput = Path::new(UINPUT_PATH);
+        Environment {
+            user: user
+                .as_ref()
+                .map_or_else(|| getuid().to_string(), |user| user.name.clone()),
+            is_root: getuid().is_root(),
+            event_devices,
+            uinput_writable: uinput
+                .exists()
+                .then(|| access(uinput, AccessFlags::W_OK).is_ok()),
+            // Built in modules are listed too.
+            uinput_module_loaded: Path::new("/sys/module/uinput").exists() || uinput.exists(),
+            input_group: membership("input"),
+            uinput_group: membership("uinput"),
+        }
+    }
+}
+
+/// How to give access through `group`, given the user's membership of it.
+fn group_fixes(group: &'static str, membership: Membership) -> Vec<Fix> {
+    match membership {
+        Membership::NoGroup => vec![
+            Fix::CreateGroup(group),
+            Fix::JoinGroup(group),
+            Fix::InstallUdevRule,
+            Fix::Relogin,
+        ],
+        Membership::NotMember => vec![Fix::JoinGroup(group), Fix::Relogin],
+        Membership::AfterRelogin => vec![Fix::Relogin],
+        // The group doesn't have access to the device.
+        Membership::Member => vec![Fix::InstallUdevRule],
+    }
+}
+
+/// Check `environment`, in the order the problems are best fixed.
+pub fn diagnose(environment: &Environment) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let unreadable = environment
+        .event_devices
+        .iter()
+        .filter(|(_, readable)| !readable)
+        .count();
+    checks.push(match (environment.event_devices.len(), unreadable) {
+        (0, _) => Check {
+            description: "No input devices found in /dev/input".to_owned(),
+            passed: false,
+            fixes: vec![],
+        },
+        (count, 0) => Check {
+            description: format!("Read access to all {} input devices", count),
+            passed: true,
+            fixes: vec![],
+        },
+        (count, unreadable) => Check {
+            description: format!(
+                "Read access to input devices: {} of {} can't be read",
+                unreadable, count
+            ),
+            passed: false,
+            fixes: group_fixes("input", environment.input_group),
+        },
+    });
+
+    checks.push(Check {
+        description: "uinput kernel module loaded".to_owned(),
+        passed: environment.uinput_module_loaded,
+        fixes: vec![Fix::LoadUinputModule],
+    });
+
+    checks.push(match environment.uinput_writable {
+        None => Check {
+            description: format!("Write access to {}, which doesn't exist", UINPUT_PATH),
+            passed: false,
+            fixes: vec![Fix::LoadUinputModule],
+        },
+        Some(writable) => Check {
+            description: format!("Write access to {}", UINPUT_PATH),
+            passed: writable,
+            fixes: group_fixes("uinput", environment.uinput_group),
+        },
+    });
+
+    for check in &mut checks {
+        if check.passed {
+            check.fixes.clear();
+        }
+    }
+    checks
+}
+
+/// A udev rule giving the input group read access to input devices, and the uinput group write
+/// access to uinput.
+pub fn udev_rule() -> String {
+    "# Installed for chorded-key-remapper, to run without root.\n\
+        KERNEL==\"event*\", SUBSYSTEM==\"input\", GROUP=\"input\", MODE=\"0660\"\n\
+        KERNEL==\"uinput\", SUBSYSTEM==\"misc\", GROUP=\"uinput\", MODE=\"0660\", \
+        OPTIONS+=\"static_node=uinput\"\n"
+        .to_owned()
+}
+
+impl Fix {
+    /// The shell commands making the fix, or what to do if it isn't a command.
+    pub fn commands(&self, user: &str) -> Vec<String> {
+        match self {
+            Fix::LoadUinputModule => vec![
+                "sudo modprobe uinput".to_owned(),
+                // So it's loaded on boot as well.
+                "echo uinput | sudo tee /etc/modules-load.d/uinput.conf".to_owned(),
+            ],
+            Fix::CreateGroup(group) => vec![format!("sudo groupadd --system {}", group)],
+            Fix::JoinGroup(group) => vec![format!("sudo usermod -aG {} {}", group, user)],
+            Fix::InstallUdevRule => vec![
+                format!("sudo tee {} <<'EOF'\n{}EOF", UDEV_RULE_PATH, udev_rule()),
+                "sudo udevadm control --reload-rules".to_owned(),
+                "sudo udevadm trigger".to_owned(),
+            ],
+            Fix::Relogin => vec!["# Log out and back in, for the new groups to apply.".to_owned()],
+        }
+    }
+}
+
+/// Every fix needed for `checks` to pass, once each, in the order to make them.
+pub fn fixes(checks: &[Check]) -> BTreeSet<Fix> {
+    checks
+        .iter()
+        .flat_map(|check| check.fixes.iter().cloned())
+        .collect()
+}
+
+/// Check the remapper can read the input devices and write to uinput without root, printing the
+/// commands to fix it if not.
+pub fn doctor() -> Result<(), Error> {
+    let environment = Environment::detect();
+    if environment.is_root {
+        println!("Running as root, which can access everything. Run as your own user to check it can too.");
+    }
+
+    let checks = diagnose(&environment);
+    for check in &checks {
+        let status = if check.passed { "ok" } else { "FAIL" };
+        println!("[{:>4}] {}", status, check.description);
+    }
+    for (path, readable) in &environment.event_devices {
+        if !readable {
+            println!("         can't read {}", path.display());
+        }
+    }
+
+    let fixes = fixes(&checks);
+    if fixes.is_empty() {
+        println!("Everything the remapper needs is in place.");
+        return Ok(());
+    }
+    println!("\nTo fix this, run:\n");
+    for fix in &fixes {
+        for command in fix.commands(&environment.user) {
+            println!("{}", command);
+        }
+    }
+    Err(Error::Message(format!(
+        "{} problems found",
+        checks.iter().filter(|check| !check.passed).count()
+    )))
+}
+
+#[cfg(test)]
+mod test_diagnose {
+    use super::*;
+
+    fn healthy() -> Environment {
+        Environment {
+            user: "me".to_owned(),
+            is_root: false,
+            event_devices: vec![
+                (PathBuf::from("/dev/input/event0"), true),
+                (PathBuf::from("/dev/input/event1"), true),
+            ],
+            uinput_writable: Some(true),
+            uinput_module_loaded: true,
+            input_group: Membership::Member,
+            uinput_group: Membership::Member,
+        }
+    }
+
+    #[test]
+    fn nothing_to_fix_when_everything_is_accessible() {
+        let checks = diagnose(&healthy());
+        assert!(checks.iter().all(|check| check.passed));
+        assert!(fixes(&checks).is_empty());
+    }
+
+    #[test]
+    fn unreadable_devices_are_fixed_by_joining_input_group() {
+        let environment = Environment {
+            event_devices: vec![(PathBuf::from("/dev/input/event0"), false)],
+            input_group: Membership::NotMember,
+            ..healthy()
+        };
+        assert_eq!(
+            fixes(&diagnose(&environment))
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![Fix::JoinGroup("input"), Fix::Relogin]
+        );
+    }
+
+    #[test]
+    fn missing_uinput_group_is_created_with_udev_rule() {
+        let environment = Environment {
+            uinput_writable: Some(false),
+            uinput_group: Membership::NoGroup,
+            ..healthy()
+        };
+        assert_eq!(
+            fixes(&diagnose(&environment))
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![
+                Fix::CreateGroup("uinput"),
+                Fix::JoinGroup("uinput"),
+                Fix::InstallUdevRule,
+                Fix::Relogin
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_uinput_needs_the_module_loaded() {
+        let environment = Environment {
+            uinput_writable: None,
+            uinput_module_loaded: false,
+            ..healthy()
+        };
+        assert_eq!(
+            fixes(&diagnose(&environment))
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![Fix::LoadUinputModule]
+        );
+    }
+
+    #[test]
+    fn udev_rule_gives_groups_access() {
+        let commands = Fix::InstallUdevRule.commands("me");
+        assert!(commands[0].starts_with(&format!("sudo tee {}", UDEV_RULE_PATH)));
+        assert!(commands[0].contains(r#"GROUP="input", MODE="0660""#));
+        assert!(commands[0].contains(r#"GROUP="uinput", MODE="0660""#));
+    }
+}
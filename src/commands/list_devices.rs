@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::parsing::{parse_config, read_config_file, Selection};
+use crate::device::{get_all_devices, DeviceClass, DeviceInfo};
+use crate::errors::Error;
+use serde::{Serialize as _, Serializer};
+use serde_derive::Serialize;
+
+const BY_ID_PATH: &str = "/dev/input/by-id";
+
+/// What's known of a device, to list.
+#[derive(Debug, Clone, Serialize)]
+pub struct Listing {
+    pub path: PathBuf,
+    /// A link to the device in /dev/input/by-id, which stays the same when it's plugged back in.
+    pub by_id: Option<PathBuf>,
+    pub name: Option<String>,
+    #[serde(serialize_with = "serialize_id")]
+    pub id: Option<(u16, u16)>,
+    pub phys: Option<String>,
+    pub uniq: Option<String>,
+    pub class: DeviceClass,
+    /// Whether another program has grabbed the device, or why it couldn't be found out. Only
+    /// found out for selected devices, as it takes grabbing the device for a moment.
+    #[serde(serialize_with = "serialize_grabbed_elsewhere")]
+    pub grabbed_elsewhere: Option<Result<bool, String>>,
+    /// Written as whether it's selected and the reason why.
+    #[serde(flatten)]
+    pub selection: Selection,
+}
+
+fn serialize_id<S: Serializer>(id: &Option<(u16, u16)>, serializer: S) -> Result<S::Ok, S::Error> {
+    id.map(format_id).serialize(serializer)
+}
+
+/// Null when it wasn't or couldn't be found out.
+fn serialize_grabbed_elsewhere<S: Serializer>(
+    grabbed_elsewhere: &Option<Result<bool, String>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    grabbed_elsewhere
+        .as_ref()
+        .and_then(|grabbed| grabbed.as_ref().ok())
+        .serialize(serializer)
+}
+
+/// The links in /dev/input/by-id, by the device node they link to.
+fn by_id_links() -> BTreeMap<PathBuf, PathBuf> {
+    let mut links: Vec<PathBuf> = fs::read_dir(BY_ID_PATH)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    // So the same link is picked for a device with more than one.
+    links.sort();
+    links.reverse();
+    links
+        .into_iter()
+        .filter_map(|link| Some((fs::canonicalize(&link).ok()?, link)))
+        .collect()
+}
+
+/// Every device which can be opened, and whether the config at `config_path` selects it. The
+/// default config is used if there's no config file.
+pub fn gather(config_path: &Path) -> Result<Vec<Listing>, Error> {
+    let config = match config_path.exists() {
+        true => read_config_file(config_path)?,
+        false => {
+            eprintln!(
+                "No config at {:?}, so showing which devices are selected by default.",
+                config_path
+            );
+            parse_config("")?
+        }
+    };
+    let links = by_id_links();
+
+    let mut listings: Vec<Listing> = get_all_devices()?
+        .into_iter()
+        .map(|mut device| {
+            let path = device.path().map(Path::to_path_buf).unwrap_or_default();
+            let selection = config.devices.selection(&device);
+            // Only selected devices are grabbed, even for a moment.
+            let grabbed_elsewhere = match selection.is_selected() {
+                true => Some(device.grabbed_elsewhere().map_err(|err| err.to_string())),
+                false => None,
+            };
+            Listing {
+                by_id: links.get(&path).cloned(),
+                name: device.name().map(str::to_owned),
+                id: device.id(),
+                phys: device.phys().map(str::to_owned),
+                uniq: device.uniq().map(str::to_owned),
+                class: device.class(),
+                grabbed_elsewhere,
+                selection,
+                path,
+            }
+        })
+        .collect();
+    // Shorter paths first, so event9 comes before event10.
+    listings.sort_by_key(|listing| (listing.path.as_os_str().len(), listing.path.clone()));
+    Ok(listings)
+}
+
+fn format_id((vendor, product): (u16, u16)) -> String {
+    format!("{:04x}:{:04x}", vendor, product)
+}
+
+/// The listing as lines of text, for reading.
+pub fn format_text(listing: &Listing) -> String {
+    let mut lines = vec![listing.path.display().to_string()];
+    let mut field = |label: &str, value: String| lines.push(format!("    {:<9} {}", label, value));
+    if let Some(by_id) = &listing.by_id {
+        field("by-id:", by_id.display().to_string());
+    }
+    field(
+        "name:",
+        match &listing.name {
+            Some(name) => format!("'{}'", name),
+            None => "UNNAMED".to_owned(),
+        },
+    );
+    if let Some(id) = listing.id {
+        field("id:", format_id(id));
+    }
+    if let Some(phys) = &listing.phys {
+        field("phys:", phys.clone());
+    }
+    if let Some(uniq) = &listing.uniq {
+        field("uniq:", uniq.clone());
+    }
+    field("class:", listing.class.to_string());
+    field(
+        "grabbed:",
+        match &listing.grabbed_elsewhere {
+            Some(Ok(true)) => "yes, by another program".to_owned(),
+            Some(Ok(false)) => "no".to_owned(),
+            Some(Err(err)) => format!("unknown, {}", err),
+            None => "not checked, as it isn't selected".to_owned(),
+        },
+    );
+    field(
+        "selected:",
+        match listing.selection.is_selected() {
+            true => format!("yes, {}", listing.selection),
+            false => format!("no, {}", listing.selection),
+        },
+    );
+    lines.join("\n")
+}
+
+/// The listings as a JSON array of objects, for scripts.
+pub fn format_json(listings: &[Listing]) -> Result<String, Error> {
+    serde_json::to_string_pretty(listings)
+        .map_err(|err| Error::Message(format!("Couldn't write the devices as JSON: {}", err)))
+}
+
+/// Print every device, what it is, and whether the config selects it.
+pub fn list_devices(config_path: &Path, json: bool) -> Result<(), Error> {
+    let listings = gather(config_path)?;
+    if json {
+        println!("{}", format_json(&listings)?);
+        return Ok(());
+    }
+    for listing in &listings {
+        println!("{}\n", format_text(listing));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_format {
+    use super::*;
+
+    fn listing() -> Listing {
+        Listing {
+            path: PathBuf::from("/dev/input/event3"),
+            by_id: Some(PathBuf::from(
+                "/dev/input/by-id/usb-Logitech_K120-event-kbd",
+            )),
+            name: Some("Logitech \"K120\"".to_owned()),
+            id: Some((0x046d, 0xc31c)),
+            phys: Some("usb-0000:00:14.0-2/input0".to_owned()),
+            uniq: None,
+            class: DeviceClass::Keyboard,
+            grabbed_elsewhere: None,
+            selection: Selection::NotIncluded,
+        }
+    }
+
+    #[test]
+    fn text_gives_each_known_property() {
+        assert_eq!(
+            format_text(&listing()),
+            "/dev/input/event3\n\
+            \x20   by-id:    /dev/input/by-id/usb-Logitech_K120-event-kbd\n\
+            \x20   name:     'Logitech \"K120\"'\n\
+            \x20   id:       046d:c31c\n\
+            \x20   phys:     usb-0000:00:14.0-2/input0\n\
+            \x20   class:    keyboard\n\
+            \x20   grabbed:  not checked, as it isn't selected\n\
+            \x20   selected: no, not in the include list"
+        );
+    }
+
+    #[test]
+    fn json_gives_null_for_unknowns() {
+        let unknown = Listing {
+            by_id: None,
+            grabbed_elsewhere: Some(Err("permission denied".to_owned())),
+            selection: Selection::OfClass(DeviceClass::Keyboard),
+            ..listing()
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&format_json(&[unknown]).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "path": "/dev/input/event3",
+                "by_id": null,
+                "name": "Logitech \"K120\"",
+                "id": "046d:c31c",
+                "phys": "usb-0000:00:14.0-2/input0",
+                "uniq": null,
+                "class": "keyboard",
+                "grabbed_elsewhere": null,
+                "selected": true,
+                "reason": "of class keyboard",
+            }])
+        );
+        assert_eq!(format_json(&[]).unwrap(), "[]");
+    }
+}
@@ -0,0 +1,5 @@
+pub mod check_config;
+pub mod doctor;
+pub mod list_devices;
+pub mod record;
+pub mod replay;
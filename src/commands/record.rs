@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::parsing::read_config_file;
+use crate::device::event_loop::EvdevSource;
+use crate::device::events::{EvdevSink, EventSink, EventSource, Poll, ReleasingSink};
+use crate::device::{get_all_devices, VirtualDevice};
+use crate::errors::Error;
+use crate::key::KeyEvent;
+use crate::trace::format_event;
+
+const VIRTUAL_KEYBOARD_NAME: &str = "Chorded Key Remapper virtual keyboard (recording)";
+
+/// Write every key event from the devices selected by the config to a trace file, timed by the
+/// kernel from the first event. With `grab`, the devices are grabbed and their events passed on
+/// unchanged through a virtual device, so that nothing else sees them twice.
+pub fn record(
+    config_path: &Path,
+    output_path: &Path,
+    grab: bool,
+    explain: bool,
+) -> Result<(), Error> {
+    let config = read_config_file(config_path)?;
+    let devices = config.devices.select_devices(get_all_devices()?, explain)?;
+    let mut output = File::create(output_path)
+        .map_err(|err| Error::Message(format!("Failed to create {:?}: {}", output_path, err)))?;
+
+    let mut source = EvdevSource::new(devices)?;
+    // Keys held when recording is stopped are released through the sink as it drops, which is
+    // before the source ungrabs the devices.
+    let mut sink = match grab {
+        false => None,
+        true => {
+            let sink = ReleasingSink::new(EvdevSink(VirtualDevice::with_all_keys(
+                VIRTUAL_KEYBOARD_NAME,
+            )?));
+            source.grab()?;
+            Some(sink)
+        }
+    };
+
+    println!("Recording to {:?}, press Ctrl-C to stop.", output_path);
+    let mut origin: Option<Duration> = None;
+    loop {
+        let event = match source.poll(None)? {
+            Poll::Event(event) => event,
+            Poll::Timeout(_) | Poll::Resync { .. } | Poll::ConfigChanged => continue,
+            Poll::Closed => return Ok(()),
+        };
+        if let Some(sink) = &mut sink {
+            sink.emit(&[event])?;
+        }
+        let origin = *origin.get_or_insert(event.time);
+        let time = event.time.saturating_sub(origin);
+        // Written as they come, so nothing is lost when recording is stopped.
+        writeln!(output, "{}", format_event(&KeyEvent { time, ..event }))?;
+    }
+}
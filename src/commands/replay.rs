@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::Path;
+
+use crate::config::parsing::read_config_file;
+use crate::device::events::{MemorySink, ScriptedSource};
+use crate::engine::Engine;
+use crate::errors::Error;
+use crate::remapper;
+use crate::trace::{format_trace, parse_trace};
+
+fn read_trace(path: &Path) -> Result<String, Error> {
+    fs::read_to_string(path)
+        .map_err(|err| Error::Message(format!("Failed to read trace {:?}: {}", path, err)))
+}
+
+/// Feed the events of a trace through the engine, and print the events it outputs. With
+/// `expected`, compare the output to the trace in that file instead, printing any differences.
+pub fn replay(config_path: &Path, trace_path: &Path, expected: Option<&Path>) -> Result<(), Error> {
+    let config = read_config_file(config_path)?;
+    let events = parse_trace(&read_trace(trace_path)?)?;
+
+    let mut engine = Engine::new(&config);
+    let mut sink = MemorySink::default();
+    remapper::run(&mut engine, &mut ScriptedSource::new(events), &mut sink)?;
+    let output = format_trace(&sink.events);
+
+    let expected_path = match expected {
+        None => {
+            print!("{}", output);
+            return Ok(());
+        }
+        Some(path) => path,
+    };
+    // Parsed and written back out, so only the events are compared, not comments or layout.
+    let expected = format_trace(&parse_trace(&read_trace(expected_path)?)?);
+    if output == expected {
+        println!("Output matches {:?}.", expected_path);
+        return Ok(());
+    }
+
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = output.lines().collect();
+    for line in diff_lines(&expected, &actual) {
+        println!("{}", line);
+    }
+    Err(Error::Message(format!(
+        "Output differs from {:?}, \"-\" lines were expected, \"+\" lines were output",
+        expected_path
+    )))
+}
+
+/// The lines of `expected` and `actual`, marked with "-" if only expected, "+" if only in
+/// actual, or indented if in both, based on their longest common subsequence.
+fn diff_lines(expected: &[&str], actual: &[&str]) -> Vec<String> {
+    // common[i][j] is the length of the longest common subsequence of expected[i..] and
+    // actual[j..].
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = match expected[i] == actual[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j < actual.len() && (i == expected.len() || common[i][j + 1] >= common[i + 1][j])
+        {
+            lines.push(format!("+ {}", actual[j]));
+            j += 1;
+        } else {
+            lines.push(format!("- {}", expected[i]));
+            i += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test_diff_lines {
+    use super::*;
+
+    #[test]
+    fn differences_are_marked() {
+        let expected = ["+KEY_UP @20ms", "-KEY_UP @100ms"];
+        let actual = ["+KEY_S @0ms", "+KEY_UP @20ms", "-KEY_UP @90ms"];
+        assert_eq!(
+            diff_lines(&expected, &actual),
+            vec![
+                "+ +KEY_S @0ms",
+                "  +KEY_UP @20ms",
+                "+ -KEY_UP @90ms",
+                "- -KEY_UP @100ms",
+            ]
+        );
+    }
+
+    #[test]
+    fn equal_lines_have_no_differences() {
+        let lines = ["+KEY_A @0ms", "-KEY_A @10ms"];
+        assert!(diff_lines(&lines, &lines)
+            .iter()
+            .all(|line| line.starts_with("  ")));
+    }
+}
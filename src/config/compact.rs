@@ -0,0 +1,256 @@
+// The compact string syntax for maps: `"s+d" = "up"` for a chord, `"j k" = "esc"` for a
+// sequence, and `"C-S-t"` for a key with modifiers.
+
+use super::deserialize::{self, KeyNames};
+use crate::errors::ConfigError;
+use crate::mapping::{FingerKey, InputKind, Map};
+use crate::position::format_finger;
+use crate::Key;
+
+/// Modifier prefixes which can be put before a key, as in `"C-S-t"`. They're upper case only, so
+/// that a lower case letter is always the letter key.
+const MODIFIER_PREFIXES: &[(&str, Key)] = &[
+    ("C", Key::KEY_LEFTCTRL),
+    ("S", Key::KEY_LEFTSHIFT),
+    ("A", Key::KEY_LEFTALT),
+    ("M", Key::KEY_LEFTMETA),
+];
+
+/// Parse the input of a map, keys separated by whitespace are a sequence, otherwise a chord.
+pub fn parse_input(
+    input: &str,
+    key_names: &KeyNames,
+) -> Result<(Vec<Key>, InputKind), ConfigError> {
+    if input.contains('+') || !input.trim().contains(char::is_whitespace) {
+        return Ok((parse_chord(input, key_names)?, InputKind::Chord));
+    }
+    let keys = input
+        .split_whitespace()
+        .map(|name| key_names.parse(name))
+        .collect::<Result<Vec<Key>, ConfigError>>()?;
+    Ok((keys, InputKind::Sequence))
+}
+
+/// Parse the output of a map, an empty string outputs nothing.
+pub fn parse_output(output: &str, key_names: &KeyNames) -> Result<Vec<Key>, ConfigError> {
+    match output.trim() {
+        "" => Ok(vec![]),
+        output => parse_chord(output, key_names),
+    }
+}
+
+/// The keys given by finger in the input or output of a map, e.g. `"left.index+s"`.
+pub fn finger_keys(text: &str, key_names: &KeyNames) -> Vec<FingerKey> {
+    text.split(|c: char| c.is_whitespace() || c == '+' || c == '-')
+        .filter_map(|name| key_names.finger(name))
+        .collect()
+}
+
+fn parse_chord(chord: &str, key_names: &KeyNames) -> Result<Vec<Key>, ConfigError> {
+    let mut keys = Vec::new();
+    for combination in chord.split('+') {
+        keys.extend(parse_combination(combination.trim(), key_names)?);
+    }
+    Ok(keys)
+}
+
+/// Parse a single key with optional modifier prefixes, e.g. `"t"` or `"C-S-t"`.
+fn parse_combination(combination: &str, key_names: &KeyNames) -> Result<Vec<Key>, ConfigError> {
+    if combination.is_empty() {
+        return Err(ConfigError::Message(
+            "Empty key in key combination".to_owned(),
+        ));
+    }
+    let mut parts: Vec<&str> = combination.split('-').collect();
+    let key = key_names.parse(parts.pop().unwrap_or_default())?;
+
+    let mut keys = parts
+        .iter()
+        .map(|prefix| {
+            MODIFIER_PREFIXES
+                .iter()
+                .find(|(name, _)| name == prefix)
+                .map(|(_, modifier)| *modifier)
+                .ok_or_else(|| {
+                    ConfigError::Message(format!(
+                        "Unknown modifier '{}' in '{}', the modifiers are C-, S-, A- and M-",
+                        prefix, combination
+                    ))
+                })
+        })
+        .collect::<Result<Vec<Key>, ConfigError>>()?;
+    keys.push(key);
+    Ok(keys)
+}
+
+/// Whether `name` can be used in the compact syntax without being split up.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == '+' || c == '-')
+}
+
+/// The short name of a key as used in the compact syntax, `None` if the key has no name or an
+/// alias in the current key names takes it, as it would be read back as a different key.
+pub fn key_name(key: Key) -> Option<String> {
+    let name = format!("{:?}", key);
+    if name.starts_with("unknown") {
+        return None;
+    }
+    let name = name.strip_prefix("KEY_").unwrap_or(&name).to_lowercase();
+    match deserialize::with_current_key_names(|names| names.is_alias(&name)) {
+        true => None,
+        false => Some(name),
+    }
+}
+
+/// The name of a key of a map, which is the finger it was given by if it was one of `fingers`.
+pub fn map_key_name(key: Key, fingers: &[FingerKey]) -> Option<String> {
+    match fingers.iter().find(|finger| finger.key == key) {
+        Some(finger) => Some(format_finger(finger.hand, finger.finger)),
+        None => key_name(key),
+    }
+}
+
+fn key_names(keys: &[Key], fingers: &[FingerKey]) -> Option<Vec<String>> {
+    keys.iter().map(|key| map_key_name(*key, fingers)).collect()
+}
+
+/// Format the input of a map in the compact syntax, if it can be expressed in it.
+pub fn format_input(map: &Map) -> Option<String> {
+    let names = key_names(&map.input, &map.fingers)?;
+    match map.kind {
+        InputKind::Chord if !names.is_empty() => Some(names.join("+")),
+        // A single key would be read back as a chord.
+        InputKind::Sequence if names.len() > 1 => Some(names.join(" ")),
+        _ => None,
+    }
+}
+
+/// Format the output of a map in the compact syntax, if it can be expressed in it.
+pub fn format_output(output: &[Key], fingers: &[FingerKey]) -> Option<String> {
+    let names = key_names(output, fingers)?;
+    let prefix = |key: &Key| {
+        MODIFIER_PREFIXES
+            .iter()
+            .find(|(_, modifier)| modifier == key)
+            .map(|(name, _)| *name)
+    };
+
+    match output.split_last() {
+        Some((last, modifiers))
+            if !modifiers.is_empty()
+                && prefix(last).is_none()
+                && modifiers.iter().all(|key| prefix(key).is_some()) =>
+        {
+            let mut parts: Vec<&str> = modifiers.iter().filter_map(prefix).collect();
+            parts.push(&names[names.len() - 1]);
+            Some(parts.join("-"))
+        }
+        _ => Some(names.join("+")),
+    }
+}
+
+/// A map as it would be written in the compact syntax, for messages.
+pub fn format_map(map: &Map) -> String {
+    match (format_input(map), format_output(&map.output, &map.fingers)) {
+        (Some(input), Some(output)) => format!("\"{}\" = \"{}\"", input, output),
+        _ => format!("{:?} = {:?}", map.input, map.output),
+    }
+}
+
+#[cfg(test)]
+mod test_compact_syntax {
+    use super::*;
+
+    fn input(input: &str) -> (Vec<Key>, InputKind) {
+        parse_input(input, &KeyNames::default()).unwrap()
+    }
+
+    fn output(output: &str) -> Vec<Key> {
+        parse_output(output, &KeyNames::default()).unwrap()
+    }
+
+    #[test]
+    fn plus_separated_keys_are_a_chord() {
+        assert_eq!(
+            input("s+d"),
+            (vec![Key::KEY_S, Key::KEY_D], InputKind::Chord)
+        );
+        assert_eq!(
+            input(" s + d "),
+            (vec![Key::KEY_S, Key::KEY_D], InputKind::Chord)
+        );
+        assert_eq!(input("s"), (vec![Key::KEY_S], InputKind::Chord));
+    }
+
+    #[test]
+    fn whitespace_separated_keys_are_a_sequence() {
+        assert_eq!(
+            input("j k"),
+            (vec![Key::KEY_J, Key::KEY_K], InputKind::Sequence)
+        );
+    }
+
+    #[test]
+    fn modifier_prefixes_are_expanded() {
+        assert_eq!(
+            output("C-S-t"),
+            vec![Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT, Key::KEY_T]
+        );
+        assert_eq!(output("A-tab"), vec![Key::KEY_LEFTALT, Key::KEY_TAB]);
+        assert_eq!(output(""), vec![]);
+    }
+
+    #[test]
+    fn lower_case_letters_are_not_modifiers() {
+        let names = KeyNames::default();
+        for combination in ["a-tab", "s-t", "c-S-t"] {
+            match parse_output(combination, &names) {
+                Err(ConfigError::Message(message)) => {
+                    assert!(message.starts_with("Unknown modifier"), "{}", message)
+                }
+                result => panic!("{}: {:?}", combination, result),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_combinations_are_errors() {
+        let names = KeyNames::default();
+        assert!(parse_output("X-t", &names).is_err());
+        assert!(parse_output("s++d", &names).is_err());
+        assert!(parse_input("", &names).is_err());
+        assert!(matches!(
+            parse_input("j nope", &names),
+            Err(ConfigError::ParseKeyError(_))
+        ));
+    }
+
+    #[test]
+    fn formatting_reverses_parsing() {
+        for (compact_input, compact_output) in [
+            ("s+d", "up"),
+            ("j k", "esc"),
+            ("leftctrl+t", "C-S-t"),
+            ("capslock", ""),
+            ("f+j", "leftshift+leftctrl"),
+        ] {
+            let (input, kind) = input(compact_input);
+            let map = Map {
+                input,
+                kind,
+                output: output(compact_output),
+                fingers: vec![],
+            };
+            assert_eq!(format_input(&map).unwrap(), compact_input);
+            assert_eq!(
+                format_output(&map.output, &map.fingers).unwrap(),
+                compact_output
+            );
+        }
+    }
+
+    #[test]
+    fn unnamed_keys_cant_be_formatted() {
+        assert_eq!(format_output(&[Key::new(0x2fe)], &[]), None);
+    }
+}
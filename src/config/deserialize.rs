@@ -1,8 +1,736 @@
 // Implement Deserialize for structs used elsewhere in the crate:
 // i.e. Map and Key, so that they can loaded from config.
 
-use std::fmt::Debug;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::str::FromStr;
+use std::sync::OnceLock;
 
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use super::compact;
+use super::schema::{self, HomeRowModsConfig};
 use crate::errors::ConfigError;
+use crate::key::KEY_CODE_COUNT;
+use crate::mapping::{FingerKey, InputKind, Map};
+use crate::position::{self, Finger, Hand, Positions};
 use crate::Key;
+
+/// Common short names which can't be derived from the kernel's key names.
+const ABBREVIATIONS: &[(&str, Key)] = &[
+    ("ctrl", Key::KEY_LEFTCTRL),
+    ("lctrl", Key::KEY_LEFTCTRL),
+    ("rctrl", Key::KEY_RIGHTCTRL),
+    ("shift", Key::KEY_LEFTSHIFT),
+    ("lshift", Key::KEY_LEFTSHIFT),
+    ("rshift", Key::KEY_RIGHTSHIFT),
+    ("alt", Key::KEY_LEFTALT),
+    ("lalt", Key::KEY_LEFTALT),
+    ("ralt", Key::KEY_RIGHTALT),
+    ("altgr", Key::KEY_RIGHTALT),
+    ("meta", Key::KEY_LEFTMETA),
+    ("lmeta", Key::KEY_LEFTMETA),
+    ("rmeta", Key::KEY_RIGHTMETA),
+    ("super", Key::KEY_LEFTMETA),
+    ("lsuper", Key::KEY_LEFTMETA),
+    ("rsuper", Key::KEY_RIGHTMETA),
+    ("win", Key::KEY_LEFTMETA),
+    ("ret", Key::KEY_ENTER),
+    ("return", Key::KEY_ENTER),
+    ("bspc", Key::KEY_BACKSPACE),
+    ("del", Key::KEY_DELETE),
+    ("ins", Key::KEY_INSERT),
+    ("pgup", Key::KEY_PAGEUP),
+    ("pgdn", Key::KEY_PAGEDOWN),
+    ("caps", Key::KEY_CAPSLOCK),
+    ("apos", Key::KEY_APOSTROPHE),
+    ("quote", Key::KEY_APOSTROPHE),
+    ("semi", Key::KEY_SEMICOLON),
+    ("period", Key::KEY_DOT),
+    ("lbrace", Key::KEY_LEFTBRACE),
+    ("rbrace", Key::KEY_RIGHTBRACE),
+    ("bslash", Key::KEY_BACKSLASH),
+];
+
+/// Resolves the names used for keys in the config file.
+///
+/// Keys can be given by their kernel name (`"KEY_S"`), or case-insensitively without the
+/// `KEY_` prefix and underscores (`"s"`, `"space"`, `"right_alt"`), by one of the
+/// `ABBREVIATIONS` (`"lctrl"`), by a user defined alias from the `[aliases]` table, or by the
+/// finger it's assigned to in the `[position]` table (`"left.index"`).
+#[derive(Debug, Default, Clone)]
+pub struct KeyNames {
+    aliases: HashMap<String, Key>,
+    positions: Positions,
+}
+
+impl KeyNames {
+    pub fn new(aliases: &BTreeMap<String, String>) -> Result<KeyNames, ConfigError> {
+        let aliases = aliases
+            .iter()
+            .map(|(alias, target)| {
+                if !compact::is_valid_name(alias) || alias.contains('.') {
+                    return Err(ConfigError::Message(format!(
+                        "Alias '{}' can't contain whitespace, '+', '-' or '.'",
+                        alias
+                    )));
+                }
+                match parse_builtin_key_name(target) {
+                    Some(key) => Ok((normalise(alias), key)),
+                    None => Err(ConfigError::ParseKeyError(format!(
+                        "'{}' (target of alias '{}')",
+                        target, alias
+                    ))),
+                }
+            })
+            .collect::<Result<HashMap<String, Key>, ConfigError>>()?;
+        Ok(KeyNames {
+            aliases,
+            positions: Positions::default(),
+        })
+    }
+
+    pub fn with_positions(self, positions: Positions) -> KeyNames {
+        KeyNames { positions, ..self }
+    }
+
+    /// Whether `name` is an alias, which takes precedence over any key of the same name.
+    pub fn is_alias(&self, name: &str) -> bool {
+        self.aliases.contains_key(&normalise(name))
+    }
+
+    /// The finger `name` refers to and the key assigned to it, if it's a finger.
+    pub fn finger(&self, name: &str) -> Option<FingerKey> {
+        let (hand, finger) = position::parse_finger(name)?;
+        Some(FingerKey {
+            key: self.positions.key(hand, finger)?,
+            hand,
+            finger,
+        })
+    }
+
+    pub fn parse(&self, name: &str) -> Result<Key, ConfigError> {
+        if let Some((hand, finger)) = position::parse_finger(name) {
+            return self.positions.key(hand, finger).ok_or_else(|| {
+                ConfigError::ParseKeyError(format!(
+                    "{} (no key is assigned to that finger in [position])",
+                    name.trim()
+                ))
+            });
+        }
+        match self.aliases.get(&normalise(name)) {
+            Some(key) => Ok(*key),
+            None => parse_builtin_key_name(name)
+                .ok_or_else(|| ConfigError::ParseKeyError(name.to_owned())),
+        }
+    }
+}
+
+/// Keys given as integers in the config are raw kernel key codes.
+pub fn key_from_code(code: i64) -> Result<Key, ConfigError> {
+    match u16::try_from(code) {
+        Ok(code) if code < KEY_CODE_COUNT => Ok(Key::new(code)),
+        _ => Err(ConfigError::ParseKeyError(format!(
+            "key code {} is out of range",
+            code
+        ))),
+    }
+}
+
+fn parse_builtin_key_name(name: &str) -> Option<Key> {
+    let upper = name.trim().to_uppercase();
+    Key::from_str(&upper)
+        .or_else(|_| Key::from_str(&format!("KEY_{}", upper)))
+        .ok()
+        .or_else(|| normalised_key_names().get(&normalise(name)).copied())
+}
+
+fn normalise(name: &str) -> String {
+    name.trim().to_lowercase().replace('_', "")
+}
+
+/// Every known key indexed by its normalised name, both with and without the `KEY_` prefix.
+fn normalised_key_names() -> &'static HashMap<String, Key> {
+    static NAMES: OnceLock<HashMap<String, Key>> = OnceLock::new();
+    NAMES.get_or_init(|| {
+        let mut names = HashMap::new();
+        for code in 0..KEY_CODE_COUNT {
+            let key = Key::new(code);
+            let name = format!("{:?}", key);
+            if name.starts_with("unknown") {
+                continue;
+            }
+            if let Some(short_name) = name.strip_prefix("KEY_") {
+                names.entry(normalise(short_name)).or_insert(key);
+            }
+            names.entry(normalise(&name)).or_insert(key);
+        }
+        for (abbreviation, key) in ABBREVIATIONS {
+            names.entry(abbreviation.to_string()).or_insert(*key);
+        }
+        names
+    })
+}
+
+thread_local! {
+    // Key names for the config currently being deserialized, see `with_key_names`.
+    static KEY_NAMES: RefCell<KeyNames> = RefCell::new(KeyNames::default());
+}
+
+/// Run `f`, resolving any keys it deserializes with `key_names`.
+///
+/// Serde gives no way to pass state into `Deserialize` impls, and the aliases are themselves
+/// part of the config, so they're made available to `ConfigKey` for the duration of the call.
+pub fn with_key_names<T>(key_names: KeyNames, f: impl FnOnce() -> T) -> T {
+    let previous = KEY_NAMES.with(|names| names.replace(key_names));
+    let result = f();
+    KEY_NAMES.with(|names| names.replace(previous));
+    result
+}
+
+/// A key as written in the config file, either a name or a raw key code, and the finger it was
+/// given by if it was.
+struct ConfigKey(Key, Option<FingerKey>);
+
+struct ConfigKeyVisitor;
+
+impl<'de> Visitor<'de> for ConfigKeyVisitor {
+    type Value = ConfigKey;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a key name or key code")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        with_current_key_names(|names| {
+            let key = names.parse(value)?;
+            Ok(ConfigKey(key, names.finger(value)))
+        })
+        .map_err(|err: ConfigError| E::custom(err))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        key_from_code(value)
+            .map(|key| ConfigKey(key, None))
+            .map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        self.visit_i64(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+impl<'de> Deserialize<'de> for ConfigKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ConfigKeyVisitor)
+    }
+}
+
+fn into_keys(keys: Vec<ConfigKey>) -> Vec<Key> {
+    keys.into_iter().map(|key| key.0).collect()
+}
+
+fn finger_keys(keys: &[ConfigKey]) -> Vec<FingerKey> {
+    keys.iter().filter_map(|key| key.1).collect()
+}
+
+pub fn with_current_key_names<T>(f: impl FnOnce(&KeyNames) -> T) -> T {
+    KEY_NAMES.with(|names| f(&names.borrow()))
+}
+
+/// Either side of a map, given as a list of keys or in the compact string syntax.
+enum MapSide {
+    Input,
+    Output,
+}
+
+struct MapSideVisitor(MapSide);
+
+impl<'de> Visitor<'de> for MapSideVisitor {
+    type Value = (Vec<Key>, InputKind, Vec<FingerKey>);
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a list of keys or a string such as \"s+d\"")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        with_current_key_names(|names| {
+            let (keys, kind) = match self.0 {
+                MapSide::Input => compact::parse_input(value, names)?,
+                MapSide::Output => (compact::parse_output(value, names)?, InputKind::Chord),
+            };
+            Ok((keys, kind, compact::finger_keys(value, names)))
+        })
+        .map_err(|err: ConfigError| E::custom(err))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        let keys = Vec::<ConfigKey>::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+        let fingers = finger_keys(&keys);
+        Ok((into_keys(keys), InputKind::Chord, fingers))
+    }
+}
+
+struct InputField((Vec<Key>, InputKind, Vec<FingerKey>));
+
+impl<'de> Deserialize<'de> for InputField {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_any(MapSideVisitor(MapSide::Input))
+            .map(InputField)
+    }
+}
+
+struct OutputField(Vec<Key>, Vec<FingerKey>);
+
+impl<'de> Deserialize<'de> for OutputField {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_any(MapSideVisitor(MapSide::Output))
+            .map(|(keys, _, fingers)| OutputField(keys, fingers))
+    }
+}
+
+impl<'de> Deserialize<'de> for Map {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde_derive::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct MapTable {
+            input: InputField,
+            output: OutputField,
+            kind: Option<InputKind>,
+        }
+
+        let map = MapTable::deserialize(deserializer)?;
+        let (input, input_kind, mut fingers) = map.input.0;
+        fingers.extend(map.output.1);
+        Ok(Map {
+            input,
+            kind: map.kind.unwrap_or(input_kind),
+            output: map.output.0,
+            fingers,
+        })
+    }
+}
+
+/// Each finger is given either the key it rests on, or a list of the keys it covers starting
+/// with the one it rests on.
+struct FingerKeys(Vec<Key>);
+
+struct FingerKeysVisitor;
+
+impl<'de> Visitor<'de> for FingerKeysVisitor {
+    type Value = FingerKeys;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a key or a list of keys")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(FingerKeys(vec![ConfigKeyVisitor.visit_str(value)?.0]))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(FingerKeys(vec![ConfigKeyVisitor.visit_i64(value)?.0]))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(FingerKeys(vec![ConfigKeyVisitor.visit_u64(value)?.0]))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        let keys = Vec::<ConfigKey>::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+        Ok(FingerKeys(into_keys(keys)))
+    }
+}
+
+impl<'de> Deserialize<'de> for FingerKeys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(FingerKeysVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Positions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde_derive::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct PositionTable {
+            #[serde(default)]
+            rows: Vec<Vec<ConfigKey>>,
+            #[serde(default)]
+            left_hand: BTreeMap<Finger, FingerKeys>,
+            #[serde(default)]
+            right_hand: BTreeMap<Finger, FingerKeys>,
+        }
+
+        fn hand_keys(
+            hand: Hand,
+            fingers: BTreeMap<Finger, FingerKeys>,
+        ) -> impl Iterator<Item = ((Hand, Finger), Vec<Key>)> {
+            fingers
+                .into_iter()
+                .map(move |(finger, keys)| ((hand, finger), keys.0))
+        }
+
+        let table = PositionTable::deserialize(deserializer)?;
+        let left = hand_keys(Hand::Left, table.left_hand);
+        let right = hand_keys(Hand::Right, table.right_hand);
+        let positions = Positions::new(left.chain(right).collect()).map_err(|(key, fingers)| {
+            de::Error::custom(format!(
+                "Key {:?} is assigned to more than one finger: {}",
+                key,
+                fingers.join(", ")
+            ))
+        })?;
+        Ok(positions.with_rows(table.rows.into_iter().map(into_keys).collect()))
+    }
+}
+
+impl<'de> Deserialize<'de> for HomeRowModsConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde_derive::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct HomeRowModsTable {
+            #[serde(default = "schema::default_tapping_term_ms")]
+            tapping_term_ms: u64,
+            #[serde(default)]
+            modifiers: toml::Table,
+        }
+
+        let table = HomeRowModsTable::deserialize(deserializer)?;
+        let modifiers = table
+            .modifiers
+            .into_iter()
+            .map(|(key, modifier)| {
+                let key =
+                    with_current_key_names(|names| names.parse(&key)).map_err(de::Error::custom)?;
+                let modifier = ConfigKey::deserialize(modifier).map_err(de::Error::custom)?;
+                Ok((key, modifier.0))
+            })
+            .collect::<Result<Vec<(Key, Key)>, D::Error>>()?;
+        Ok(HomeRowModsConfig {
+            tapping_term_ms: table.tapping_term_ms,
+            modifiers,
+        })
+    }
+}
+
+/// A single key, for use with `#[serde(deserialize_with)]`.
+pub fn deserialize_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+    ConfigKey::deserialize(deserializer).map(|key| key.0)
+}
+
+/// A chord, as a list of keys or in the compact syntax, e.g. `"esc+backspace+enter"`.
+pub fn deserialize_chord<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Key>, D::Error> {
+    let InputField((keys, kind, _)) = InputField::deserialize(deserializer)?;
+    if kind == InputKind::Sequence && keys.len() > 1 {
+        return Err(de::Error::custom(
+            "expected a chord, with its keys joined by \"+\"",
+        ));
+    }
+    Ok(keys)
+}
+
+/// `maps` is either a list of maps, or a table in the compact syntax, e.g. `"s+d" = "up"`.
+pub fn deserialize_maps<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<Map>>, D::Error> {
+    struct MapsVisitor;
+
+    impl<'de> Visitor<'de> for MapsVisitor {
+        type Value = Vec<Map>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                formatter,
+                "a list of maps or a table of \"input\" = \"output\" pairs"
+            )
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+            Vec::<Map>::deserialize(de::value::SeqAccessDeserializer::new(seq))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut entries: A) -> Result<Self::Value, A::Error> {
+            let mut maps = Vec::new();
+            while let Some((input, output)) = entries.next_entry::<String, OutputField>()? {
+                let ((keys, kind), mut fingers) = with_current_key_names(|names| {
+                    let parsed = compact::parse_input(&input, names)?;
+                    Ok((parsed, compact::finger_keys(&input, names)))
+                })
+                .map_err(|err: ConfigError| de::Error::custom(err))?;
+                fingers.extend(output.1);
+                maps.push(Map {
+                    input: keys,
+                    kind,
+                    output: output.0,
+                    fingers,
+                });
+            }
+            Ok(maps)
+        }
+    }
+
+    deserializer.deserialize_any(MapsVisitor).map(Some)
+}
+
+#[cfg(test)]
+mod test_key_names {
+    use super::*;
+
+    fn parse(name: &str) -> Result<Key, ConfigError> {
+        KeyNames::default().parse(name)
+    }
+
+    #[test]
+    fn kernel_names_are_accepted() {
+        assert_eq!(parse("KEY_S").unwrap(), Key::KEY_S);
+        assert_eq!(parse("KEY_LEFTCTRL").unwrap(), Key::KEY_LEFTCTRL);
+        assert_eq!(parse("BTN_LEFT").unwrap(), Key::BTN_LEFT);
+    }
+
+    #[test]
+    fn short_names_are_case_insensitive() {
+        assert_eq!(parse("s").unwrap(), Key::KEY_S);
+        assert_eq!(parse("S").unwrap(), Key::KEY_S);
+        assert_eq!(parse("space").unwrap(), Key::KEY_SPACE);
+        assert_eq!(parse("Up").unwrap(), Key::KEY_UP);
+        assert_eq!(parse("1").unwrap(), Key::KEY_1);
+        assert_eq!(parse("right_alt").unwrap(), Key::KEY_RIGHTALT);
+    }
+
+    #[test]
+    fn abbreviations_are_accepted() {
+        assert_eq!(parse("lctrl").unwrap(), Key::KEY_LEFTCTRL);
+        assert_eq!(parse("RShift").unwrap(), Key::KEY_RIGHTSHIFT);
+        assert_eq!(parse("bspc").unwrap(), Key::KEY_BACKSPACE);
+    }
+
+    #[test]
+    fn unknown_names_give_parse_key_error() {
+        let err = parse("KEY_NOT_A_KEY").unwrap_err();
+        assert!(matches!(err, ConfigError::ParseKeyError(_)));
+        assert_eq!(err.to_string(), "Unrecognised key: KEY_NOT_A_KEY");
+    }
+
+    #[test]
+    fn aliases_take_precedence_and_are_case_insensitive() {
+        let names = KeyNames::new(&BTreeMap::from([
+            ("Nav".to_owned(), "capslock".to_owned()),
+            ("s".to_owned(), "KEY_D".to_owned()),
+        ]))
+        .unwrap();
+        assert_eq!(names.parse("nav").unwrap(), Key::KEY_CAPSLOCK);
+        assert_eq!(names.parse("s").unwrap(), Key::KEY_D);
+    }
+
+    #[test]
+    fn alias_to_unknown_key_is_an_error() {
+        let result = KeyNames::new(&BTreeMap::from([("nav".to_owned(), "nope".to_owned())]));
+        assert!(matches!(result, Err(ConfigError::ParseKeyError(_))));
+    }
+
+    #[test]
+    fn key_codes_must_be_in_range() {
+        assert_eq!(key_from_code(31).unwrap(), Key::KEY_S);
+        assert!(key_from_code(-1).is_err());
+        assert!(key_from_code(KEY_CODE_COUNT as i64).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_map_deserialize {
+    use super::*;
+
+    fn parse_map(content: &str, key_names: KeyNames) -> Result<Map, toml::de::Error> {
+        with_key_names(key_names, || toml::from_str::<Map>(content))
+    }
+
+    #[test]
+    fn mixed_key_syntaxes_in_one_map() {
+        let names = KeyNames::new(&BTreeMap::from([(
+            "nav".to_owned(),
+            "KEY_CAPSLOCK".to_owned(),
+        )]))
+        .unwrap();
+        let map = parse_map(
+            r#"input = ["KEY_S", "d", 36, "nav"]
+output = ["up"]"#,
+            names,
+        )
+        .unwrap();
+        assert_eq!(
+            map,
+            Map {
+                input: vec![Key::KEY_S, Key::KEY_D, Key::KEY_J, Key::KEY_CAPSLOCK],
+                kind: InputKind::Chord,
+                output: vec![Key::KEY_UP],
+                fingers: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_key_error_names_the_key() {
+        let err = parse_map(
+            r#"input = ["KEY_S"]
+output = ["KEY_NOT_A_KEY"]"#,
+            KeyNames::default(),
+        )
+        .unwrap_err();
+        assert!(err.message().contains("Unrecognised key: KEY_NOT_A_KEY"));
+    }
+
+    #[test]
+    fn aliases_are_only_in_scope_within_with_key_names() {
+        let names = KeyNames::new(&BTreeMap::from([(
+            "nav".to_owned(),
+            "KEY_CAPSLOCK".to_owned(),
+        )]))
+        .unwrap();
+        let content = r#"input = ["nav"]
+output = ["up"]"#;
+        assert!(parse_map(content, names).is_ok());
+        assert!(toml::from_str::<Map>(content).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_maps_deserialize {
+    use super::*;
+
+    #[derive(serde_derive::Deserialize)]
+    struct Maps {
+        #[serde(deserialize_with = "deserialize_maps")]
+        maps: Option<Vec<Map>>,
+    }
+
+    fn parse_maps(content: &str) -> Result<Vec<Map>, toml::de::Error> {
+        with_key_names(KeyNames::default(), || toml::from_str::<Maps>(content))
+            .map(|maps| maps.maps.unwrap())
+    }
+
+    #[test]
+    fn compact_table_gives_same_maps_as_list() {
+        let compact = parse_maps(
+            r#"[maps]
+"s+d" = "up"
+"j k" = "esc"
+"#,
+        )
+        .unwrap();
+        let list = parse_maps(
+            r#"maps = [
+    {input = ["KEY_S", "KEY_D"], output = ["KEY_UP"]},
+    {input = ["KEY_J", "KEY_K"], kind = "sequence", output = ["KEY_ESC"]},
+]"#,
+        )
+        .unwrap();
+        assert_eq!(compact, list);
+    }
+
+    #[test]
+    fn list_entries_can_use_compact_strings() {
+        let maps = parse_maps(r#"maps = [{input = "j k", output = "C-S-t"}]"#).unwrap();
+        assert_eq!(
+            maps,
+            vec![Map {
+                input: vec![Key::KEY_J, Key::KEY_K],
+                kind: InputKind::Sequence,
+                output: vec![Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT, Key::KEY_T],
+                fingers: vec![],
+            }]
+        );
+    }
+
+    #[test]
+    fn unknown_key_in_compact_table_is_an_error() {
+        let err = parse_maps(
+            r#"[maps]
+"s+nope" = "up"
+"#,
+        )
+        .unwrap_err();
+        assert!(err.message().contains("Unrecognised key: nope"));
+    }
+}
+
+#[cfg(test)]
+mod test_positions {
+    use super::*;
+
+    fn positions() -> Positions {
+        toml::from_str(
+            r#"
+[left_hand]
+little = "KEY_A"
+index = "f"
+
+[right_hand]
+index = ["j", "h", "u"]
+pinky = "semi"
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn fingers_are_assigned_keys() {
+        let positions = positions();
+        assert_eq!(positions.key(Hand::Left, Finger::Little), Some(Key::KEY_A));
+        assert_eq!(
+            positions.key(Hand::Right, Finger::Little),
+            Some(Key::KEY_SEMICOLON)
+        );
+        assert_eq!(positions.key(Hand::Right, Finger::Thumb), None);
+        assert_eq!(
+            positions.finger_of(Key::KEY_J),
+            Some((Hand::Right, Finger::Index))
+        );
+    }
+
+    #[test]
+    fn fingers_can_cover_several_keys() {
+        let positions = positions();
+        assert_eq!(positions.key(Hand::Right, Finger::Index), Some(Key::KEY_J));
+        assert_eq!(
+            positions.finger_of(Key::KEY_U),
+            Some((Hand::Right, Finger::Index))
+        );
+        assert!(positions.is_resting_key(Key::KEY_J));
+        assert!(!positions.is_resting_key(Key::KEY_H));
+    }
+
+    #[test]
+    fn fingers_can_be_used_in_place_of_keys() {
+        let names = KeyNames::default().with_positions(positions());
+        assert_eq!(names.parse("left.index").unwrap(), Key::KEY_F);
+        assert_eq!(names.parse("Right.Pinky").unwrap(), Key::KEY_SEMICOLON);
+    }
+
+    #[test]
+    fn unassigned_finger_is_an_error() {
+        let names = KeyNames::default().with_positions(positions());
+        let err = names.parse("right.thumb").unwrap_err();
+        assert!(matches!(err, ConfigError::ParseKeyError(_)));
+        assert!(err.to_string().contains("right.thumb"));
+    }
+
+    #[test]
+    fn key_assigned_to_two_fingers_is_an_error() {
+        let result = toml::from_str::<Positions>(
+            r#"
+[left_hand]
+thumb = "space"
+[right_hand]
+thumb = "space"
+"#,
+        );
+        assert!(result
+            .unwrap_err()
+            .message()
+            .contains("left.thumb, right.thumb"));
+    }
+}
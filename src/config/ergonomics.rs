@@ -0,0 +1,256 @@
+// Score how comfortable the chords of a config are to play, using the fingers which the
+// position table assigns to each key.
+
+use std::fmt;
+
+use crate::mapping::{InputKind, Map};
+use crate::position::{self, Finger, Hand, Positions};
+use crate::Key;
+
+/// More keys than this on one hand are awkward to press together.
+pub const MAX_KEYS_PER_HAND: usize = 3;
+
+const MAX_SCORE: u32 = 100;
+const PENALTY_PER_EXTRA_KEY_ON_HAND: u32 = 20;
+const PENALTY_PER_UNCOVERED_KEY: u32 = 25;
+const PENALTY_PER_STRETCHED_KEY: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// Keys which one finger would have to press at the same time.
+    SameFinger {
+        hand: Hand,
+        finger: Finger,
+        keys: Vec<Key>,
+    },
+    /// More keys on one hand than `MAX_KEYS_PER_HAND`.
+    TooManyKeysOnHand { hand: Hand, count: usize },
+    /// Keys which a finger covers but doesn't rest on, so has to stretch to.
+    StretchedKeys(Vec<Key>),
+    /// Keys which no finger covers in the position table.
+    UncoveredKeys(Vec<Key>),
+}
+
+impl Issue {
+    pub fn is_impossible(&self) -> bool {
+        matches!(self, Issue::SameFinger { .. })
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::SameFinger { hand, finger, keys } => write!(
+                f,
+                "impossible: {:?} are all pressed by {}",
+                keys,
+                position::format_finger(*hand, *finger)
+            ),
+            Issue::TooManyKeysOnHand { hand, count } => write!(
+                f,
+                "awkward: {} keys on the {} hand, at most {} are comfortable",
+                count, hand, MAX_KEYS_PER_HAND
+            ),
+            Issue::StretchedKeys(keys) => {
+                write!(
+                    f,
+                    "stretch: {:?} aren't the keys their fingers rest on",
+                    keys
+                )
+            }
+            Issue::UncoveredKeys(keys) => {
+                write!(f, "unknown reach: {:?} aren't covered by any finger", keys)
+            }
+        }
+    }
+}
+
+/// How comfortable a chord is to play, from 0 (impossible) to 100.
+#[derive(Debug)]
+pub struct ChordReport<'a> {
+    pub map: &'a Map,
+    pub score: u32,
+    pub issues: Vec<Issue>,
+}
+
+impl ChordReport<'_> {
+    pub fn is_impossible(&self) -> bool {
+        self.issues.iter().any(Issue::is_impossible)
+    }
+}
+
+pub fn check_chord<'a>(map: &'a Map, positions: &Positions) -> ChordReport<'a> {
+    let mut issues = Vec::new();
+    let mut penalty = 0;
+
+    let mut fingers: Vec<(Hand, Finger, Vec<Key>)> = Vec::new();
+    let mut stretched = Vec::new();
+    let mut uncovered = Vec::new();
+    for key in &map.input {
+        match positions.finger_of(*key) {
+            None => uncovered.push(*key),
+            Some((hand, finger)) => {
+                if !positions.is_resting_key(*key) {
+                    stretched.push(*key);
+                }
+                match fingers.iter_mut().find(|(other_hand, other_finger, _)| {
+                    (*other_hand, *other_finger) == (hand, finger)
+                }) {
+                    Some((_, _, keys)) => keys.push(*key),
+                    None => fingers.push((hand, finger, vec![*key])),
+                }
+            }
+        }
+    }
+
+    for (hand, finger, keys) in &fingers {
+        if keys.len() > 1 {
+            issues.push(Issue::SameFinger {
+                hand: *hand,
+                finger: *finger,
+                keys: keys.clone(),
+            });
+        }
+    }
+
+    for hand in [Hand::Left, Hand::Right] {
+        let count = map
+            .input
+            .iter()
+            .filter(|key| positions.hand_of(**key) == Some(hand))
+            .count();
+        if count > MAX_KEYS_PER_HAND {
+            penalty += PENALTY_PER_EXTRA_KEY_ON_HAND * (count - MAX_KEYS_PER_HAND) as u32;
+            issues.push(Issue::TooManyKeysOnHand { hand, count });
+        }
+    }
+
+    if !stretched.is_empty() {
+        penalty += PENALTY_PER_STRETCHED_KEY * stretched.len() as u32;
+        issues.push(Issue::StretchedKeys(stretched));
+    }
+
+    if !uncovered.is_empty() {
+        penalty += PENALTY_PER_UNCOVERED_KEY * uncovered.len() as u32;
+        issues.push(Issue::UncoveredKeys(uncovered));
+    }
+
+    let score = match issues.iter().any(Issue::is_impossible) {
+        true => 0,
+        false => MAX_SCORE.saturating_sub(penalty),
+    };
+    ChordReport { map, score, issues }
+}
+
+/// Check every chord of more than one key in `maps`, least comfortable first.
+pub fn check_chords<'a>(maps: &'a [Map], positions: &Positions) -> Vec<ChordReport<'a>> {
+    let mut reports: Vec<ChordReport> = maps
+        .iter()
+        .filter(|map| map.kind == InputKind::Chord && map.input.len() > 1)
+        .map(|map| check_chord(map, positions))
+        .collect();
+    reports.sort_by_key(|report| report.score);
+    reports
+}
+
+#[cfg(test)]
+mod test_check_chord {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn positions() -> Positions {
+        Positions::new(BTreeMap::from([
+            ((Hand::Left, Finger::Little), vec![Key::KEY_A]),
+            ((Hand::Left, Finger::Ring), vec![Key::KEY_S]),
+            ((Hand::Left, Finger::Middle), vec![Key::KEY_D]),
+            ((Hand::Left, Finger::Index), vec![Key::KEY_F, Key::KEY_G]),
+            ((Hand::Right, Finger::Index), vec![Key::KEY_J]),
+        ]))
+        .unwrap()
+    }
+
+    fn chord(input: Vec<Key>) -> Map {
+        Map {
+            input,
+            kind: InputKind::Chord,
+            output: vec![Key::KEY_ESC],
+            fingers: vec![],
+        }
+    }
+
+    #[test]
+    fn resting_keys_on_different_fingers_score_full_marks() {
+        let map = chord(vec![Key::KEY_D, Key::KEY_F, Key::KEY_J]);
+        let report = check_chord(&map, &positions());
+        assert_eq!(report.score, MAX_SCORE);
+        assert!(report.issues.is_empty());
+    }
+
+    #[test]
+    fn keys_on_same_finger_are_impossible() {
+        let map = chord(vec![Key::KEY_F, Key::KEY_G]);
+        let report = check_chord(&map, &positions());
+        assert_eq!(report.score, 0);
+        assert!(report.is_impossible());
+        assert_eq!(
+            report.issues,
+            vec![
+                Issue::SameFinger {
+                    hand: Hand::Left,
+                    finger: Finger::Index,
+                    keys: vec![Key::KEY_F, Key::KEY_G],
+                },
+                Issue::StretchedKeys(vec![Key::KEY_G])
+            ]
+        );
+    }
+
+    #[test]
+    fn too_many_keys_on_one_hand_are_penalised() {
+        let map = chord(vec![Key::KEY_A, Key::KEY_S, Key::KEY_D, Key::KEY_F]);
+        let report = check_chord(&map, &positions());
+        assert_eq!(report.score, MAX_SCORE - PENALTY_PER_EXTRA_KEY_ON_HAND);
+        assert_eq!(
+            report.issues,
+            vec![Issue::TooManyKeysOnHand {
+                hand: Hand::Left,
+                count: 4
+            }]
+        );
+    }
+
+    #[test]
+    fn uncovered_and_stretched_keys_are_penalised() {
+        let map = chord(vec![Key::KEY_G, Key::KEY_Q]);
+        let report = check_chord(&map, &positions());
+        assert_eq!(
+            report.score,
+            MAX_SCORE - PENALTY_PER_STRETCHED_KEY - PENALTY_PER_UNCOVERED_KEY
+        );
+        assert_eq!(
+            report.issues,
+            vec![
+                Issue::StretchedKeys(vec![Key::KEY_G]),
+                Issue::UncoveredKeys(vec![Key::KEY_Q])
+            ]
+        );
+    }
+
+    #[test]
+    fn only_chords_are_checked_least_comfortable_first() {
+        let maps = vec![
+            chord(vec![Key::KEY_D, Key::KEY_J]),
+            chord(vec![Key::KEY_F, Key::KEY_G]),
+            chord(vec![Key::KEY_D]),
+            Map {
+                input: vec![Key::KEY_F, Key::KEY_G],
+                kind: InputKind::Sequence,
+                output: vec![Key::KEY_ESC],
+                fingers: vec![],
+            },
+        ];
+        let reports = check_chords(&maps, &positions());
+        let scores: Vec<u32> = reports.iter().map(|report| report.score).collect();
+        assert_eq!(scores, vec![0, MAX_SCORE]);
+    }
+}
@@ -1,3 +1,6 @@
+pub mod compact;
 mod deserialize;
+pub mod ergonomics;
 pub mod parsing;
 pub mod schema;
+mod serialize;
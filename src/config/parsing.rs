@@ -1,12 +1,19 @@
-use super::schema::{Config, DevicesConfig};
+use super::compact;
+use super::deserialize::{self, KeyNames};
+use super::schema::{Config, DeviceMatcher, DevicesConfig};
 use crate::auxiliary::device_filtering::FilterableDevices;
-use crate::device::DeviceInfo;
+use crate::device::{DeviceClass, DeviceInfo};
 use crate::errors::ConfigError;
 use crate::errors::DeviceError;
+use crate::position::Positions;
+use crate::privileges;
 
 use log::log_enabled;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
 use std::{fs, path::Path};
-use toml;
 
 pub fn read_config_file(path: &Path) -> Result<Config, ConfigError> {
     let binding = match fs::read_to_string(path) {
@@ -16,81 +23,353 @@ pub fn read_config_file(path: &Path) -> Result<Config, ConfigError> {
             path.as_os_str()
         ))),
     }?;
-    let content = binding.as_str();
-    let config: Config = toml::from_str(content)?;
+    parse_config(binding.as_str())
+}
+
+pub fn parse_config(content: &str) -> Result<Config, ConfigError> {
+    let table: toml::Table = toml::from_str(content)?;
+
+    // Aliases need to be known before any of the keys which might use them are deserialized.
+    let aliases: BTreeMap<String, String> = match table.get("aliases") {
+        None => BTreeMap::new(),
+        Some(aliases) => aliases.clone().try_into()?,
+    };
+    let mut key_names = KeyNames::new(&aliases)?;
+
+    // Likewise for fingers, which can be used in place of keys once the position table is read.
+    if let Some(position) = table.get("position") {
+        let positions: Positions =
+            deserialize::with_key_names(key_names.clone(), || position.clone().try_into())?;
+        key_names = key_names.with_positions(positions);
+    }
+
+    let config: Config =
+        deserialize::with_key_names(key_names, || toml::Value::Table(table).try_into())?;
+    config.validate()?;
     Ok(config)
 }
 
+impl Config {
+    /// Check the parts of the config which depend on each other.
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(home_row_mods) = &self.home_row_mods {
+            for (key, _) in &home_row_mods.modifiers {
+                if self.position.hand_of(*key).is_none() {
+                    return Err(ConfigError::Message(format!(
+                        "Home row mod key {:?} has to be assigned to a finger in [position], \
+                        to know which hand it's on",
+                        key
+                    )));
+                }
+            }
+        }
+        if let Some(privileges) = &self.privileges {
+            if privileges.group.is_some() && privileges.user.is_none() {
+                return Err(ConfigError::Message(
+                    "[privileges] group needs a user to switch to as well".to_owned(),
+                ));
+            }
+            if privileges.seccomp && !privileges::SECCOMP_SUPPORTED {
+                return Err(ConfigError::Message(
+                    "[privileges] seccomp is unsupported on this architecture".to_owned(),
+                ));
+            }
+        }
+        let devices = &self.devices;
+        for matcher in devices.include.iter().chain(&devices.exclude).flatten() {
+            if let DeviceMatcher::Properties(properties) = matcher {
+                properties.check().map_err(ConfigError::Message)?;
+            }
+        }
+        let maps = self
+            .mappings
+            .iter()
+            .flat_map(|mappings| mappings.maps.iter().flatten());
+        for map in maps {
+            map.check().map_err(|problem| {
+                ConfigError::Message(format!("The map {} {}", compact::format_map(map), problem))
+            })?;
+        }
+        if self.escape_chord.len() == 1 {
+            return Err(ConfigError::Message(
+                "escape_chord needs at least two keys, or none to turn it off, as a single key \
+                would stop the remapper whenever it's typed"
+                    .to_owned(),
+            ));
+        }
+        if self.mirror.is_some() && self.position.mirror_pairs().is_empty() {
+            return Err(ConfigError::Message(
+                "[mirror] needs keys assigned to the same fingers of both hands in [position]"
+                    .to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Write out `config` such that `parse_config` reads back the same config.
+#[cfg(test)]
+pub fn config_to_string(config: &Config) -> Result<String, ConfigError> {
+    // Keys whose names are taken by aliases are written by key code instead.
+    let key_names = KeyNames::new(&config.aliases)?;
+    Ok(deserialize::with_key_names(key_names, || {
+        toml::to_string(config)
+    })?)
+}
+
 impl DevicesConfig {
+    /// The devices of `all_devices` the config selects, or why none are.
     pub fn extract_devices_to_remap<T: DeviceInfo>(
         self,
         all_devices: Vec<T>,
     ) -> Result<Vec<T>, DeviceError> {
-        let keyboards = match self.include {
-            // Automatically select all non-virtual keyboards if no specific devices are specified in the config.
-            None => match all_devices.extract_devices_whose_name_doesnt_contain("virtual") {
-                None => Err(DeviceError::DevicesNotFound(format!(
-                    "No non-virtual devices fond in existing devices."
-                ))),
-
-                Some(non_virtual_devs) => match non_virtual_devs.extract_keyboards() {
-                    None => Err(DeviceError::DevicesNotFound(format!(
-                        "No non-virtual keyboards found in existing devices."
-                    ))),
-                    Some(non_virtual_keyboards) => Ok(non_virtual_keyboards),
-                },
-            },
+        let selections: Vec<Selection> = all_devices
+            .iter()
+            .map(|device| self.selection(device))
+            .collect();
+        let devices = match all_devices.extract_selected_devices(&self) {
+            Some(devices) => devices,
+            None => return Err(self.none_selected(&selections)),
+        };
 
-            // If specific devices are specified in the config, select those.
-            Some(include_names) => match all_devices.extract_named_devices(&include_names) {
-                None => Err(DeviceError::DevicesNotFound(format!(
-                    "No devices found which match names: {}",
-                    format_many_device_names(&include_names)
-                ))),
-
-                Some(devices) => {
-                    if log_enabled!(log::Level::Info) {
-                        if devices.len() != include_names.len() {
-                            let found: Vec<&str> = devices
-                                .iter()
-                                .map(|dev| match dev.name() {
-                                    Some(name) => name,
-                                    None => "UNNAMED",
-                                })
-                                .collect();
-
-                            let missing: Vec<String> = include_names
-                                .iter()
-                                .filter(|name| !found.contains(&name.as_str()))
-                                .map(|name| name.to_owned())
-                                .collect();
-
-                            log::info!(
-                                "Not all named devices where found. Couldn't find: {}",
-                                format_many_device_names(&missing)
-                            );
-                        }
-                    }
-                    Ok(devices)
-                }
-            },
-        }?;
-
-        match self.exclude {
-            None => Ok(keyboards),
-            Some(exclude_names) => match keyboards.remove_named_devices(&exclude_names) {
-                Some(keyboards) => Ok(keyboards),
+        if let Some(include) = &self.include {
+            let missing: Vec<&DeviceMatcher> = include
+                .iter()
+                .filter(|matcher| !devices.iter().any(|dev| matcher.matches(dev)))
+                .collect();
+            if log_enabled!(log::Level::Info) && !missing.is_empty() {
+                log::info!(
+                    "Not all named devices where found. Couldn't find: {}",
+                    format_many_device_names(&missing)
+                );
+            }
+        }
+        Ok(devices)
+    }
 
-                None => Err(DeviceError::DevicesNotFound(format!(
+    /// Why no device was selected, given the `selections` of every device.
+    fn none_selected(&self, selections: &[Selection]) -> DeviceError {
+        let exclude = self.exclude.as_deref().unwrap_or_default();
+        DeviceError::DevicesNotFound(match (&self.include, &self.classes) {
+            _ if selections
+                .iter()
+                .any(|selection| matches!(selection, Selection::Excluded(_))) =>
+            {
+                format!(
                     "No devices left after filtering out excluded devices: {}",
-                    format_many_device_names(&exclude_names)
-                ))),
+                    format_many_device_names(exclude)
+                )
+            }
+            (Some(include), _) => format!(
+                "No devices found which match names: {}",
+                format_many_device_names(include)
+            ),
+            // Without an include list, non-virtual keyboards are selected, or non-virtual devices
+            // of the classes given.
+            (None, _)
+                if selections
+                    .iter()
+                    .all(|selection| selection == &Selection::Virtual) =>
+            {
+                "No non-virtual devices fond in existing devices.".to_owned()
+            }
+            (None, None) => "No non-virtual keyboards found in existing devices.".to_owned(),
+            (None, Some(classes)) => format!(
+                "No non-virtual devices of the classes {} found in existing devices.",
+                format_many_device_names(classes)
+            ),
+        })
+    }
+}
+
+impl DevicesConfig {
+    /// Why each of `devices` is selected or not, and the devices which may have been meant by
+    /// include names matching none.
+    pub fn explain_selection<T: DeviceInfo>(&self, devices: &[T]) -> Vec<String> {
+        let mut lines = vec!["Device selection:".to_owned()];
+        for device in devices {
+            let selection = self.selection(device);
+            let status = match selection.is_selected() {
+                true => "selected",
+                false => "not selected",
+            };
+            lines.push(format!(
+                "  {:<13} '{}': {}",
+                status,
+                device.to_string(),
+                selection
+            ));
+        }
+        for (name, suggestions) in self.near_misses(devices) {
+            let suggestions: Vec<String> = suggestions
+                .iter()
+                .map(|suggestion| format!("'{}'", suggestion))
+                .collect();
+            lines.push(format!(
+                "'{}' in the include list matches no device, did you mean {}?",
+                name,
+                suggestions.join(" or ")
+            ));
+        }
+        lines
+    }
+
+    /// The devices selected from `devices`, explaining why each is selected or not if `explain`
+    /// is set, or saying how to if none are.
+    pub fn select_devices<T: DeviceInfo>(
+        &self,
+        devices: Vec<T>,
+        explain: bool,
+    ) -> Result<Vec<T>, DeviceError> {
+        if explain {
+            for line in self.explain_selection(&devices) {
+                println!("{}", line);
+            }
+        }
+        match self.clone().extract_devices_to_remap(devices) {
+            Err(DeviceError::DevicesNotFound(message)) if !explain => {
+                Err(DeviceError::DevicesNotFound(format!(
+                    "{}\nRun with --explain to see why each device was or wasn't selected.",
+                    message
+                )))
+            }
+            result => result,
+        }
+    }
+}
+
+/// Whether the config selects a device to remap, and which rule decided it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selection {
+    /// Selected for being of one of the classes selected when there's no include list.
+    OfClass(DeviceClass),
+    /// Selected by this entry of the include list.
+    Included(DeviceMatcher),
+    /// Its name contains "virtual", so it isn't selected without an include list.
+    Virtual,
+    /// Not of a class selected when there's no include list, e.g. not a keyboard.
+    NotOfClass(DeviceClass),
+    NotIncluded,
+    /// Would be selected, but is left out by this entry of the exclude list.
+    Excluded(DeviceMatcher),
+}
+
+impl Selection {
+    pub fn is_selected(&self) -> bool {
+        matches!(self, Selection::OfClass(_) | Selection::Included(_))
+    }
+}
+
+/// Written as whether it's selected, and the reason why.
+impl Serialize for Selection {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut selection = serializer.serialize_struct("Selection", 2)?;
+        selection.serialize_field("selected", &self.is_selected())?;
+        selection.serialize_field("reason", &self.to_string())?;
+        selection.end()
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Selection::OfClass(class) => write!(f, "of class {}", class),
+            Selection::Included(matcher) => write!(f, "in the include list as '{}'", matcher),
+            Selection::Virtual => write!(f, "its name contains \"virtual\""),
+            Selection::NotOfClass(class) => write!(
+                f,
+                "of class {}, which isn't selected without an include list",
+                class
+            ),
+            Selection::NotIncluded => write!(f, "not in the include list"),
+            Selection::Excluded(matcher) => write!(f, "in the exclude list as '{}'", matcher),
+        }
+    }
+}
+
+impl DevicesConfig {
+    /// Whether `device` is selected by `extract_devices_to_remap`, and why.
+    pub fn selection<T: DeviceInfo>(&self, device: &T) -> Selection {
+        let selection = self.inclusion(device);
+        if !selection.is_selected() {
+            return selection;
+        }
+        match self
+            .exclude
+            .iter()
+            .flatten()
+            .find(|matcher| matcher.matches(device))
+        {
+            Some(matcher) => Selection::Excluded(matcher.clone()),
+            None => selection,
+        }
+    }
+
+    /// Whether `device` is selected before the exclude list is applied.
+    fn inclusion<T: DeviceInfo>(&self, device: &T) -> Selection {
+        match &self.include {
+            Some(include) => match include.iter().find(|matcher| matcher.matches(device)) {
+                Some(matcher) => Selection::Included(matcher.clone()),
+                None => Selection::NotIncluded,
             },
+            None if device.to_string().to_lowercase().contains("virtual") => Selection::Virtual,
+            None => {
+                let class = device.class();
+                let selected = match &self.classes {
+                    None => class == DeviceClass::Keyboard,
+                    Some(classes) => classes.contains(&class),
+                };
+                match selected {
+                    true => Selection::OfClass(class),
+                    false => Selection::NotOfClass(class),
+                }
+            }
         }
     }
 }
 
-fn format_many_device_names(names: &Vec<String>) -> String {
+/// How alike two device names have to be, from 0 to 1, for one to be suggested for the other.
+const NEAR_MISS_SIMILARITY: f64 = 0.7;
+
+impl DevicesConfig {
+    /// The names of devices much like each name in the include list which matches no device,
+    /// best first, e.g. as the name was misspelt.
+    pub fn near_misses<T: DeviceInfo>(&self, devices: &[T]) -> Vec<(String, Vec<String>)> {
+        let names = self.include.iter().flatten().filter_map(|matcher| {
+            let name = match matcher {
+                DeviceMatcher::Name(name) => name,
+                DeviceMatcher::Properties(properties) => properties.name.as_ref()?,
+            };
+            match devices.iter().any(|device| matcher.matches(device)) {
+                true => None,
+                false => Some(name),
+            }
+        });
+        names
+            .map(|name| {
+                let mut suggestions: Vec<(f64, String)> = devices
+                    .iter()
+                    .filter_map(|device| device.name())
+                    .map(|device_name| {
+                        let similarity = strsim::normalized_levenshtein(
+                            &name.to_lowercase(),
+                            &device_name.to_lowercase(),
+                        );
+                        (similarity, device_name.to_owned())
+                    })
+                    .filter(|(similarity, _)| *similarity >= NEAR_MISS_SIMILARITY)
+                    .collect();
+                suggestions.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+                suggestions.dedup_by(|a, b| a.1 == b.1);
+                let suggestions = suggestions.into_iter().map(|(_, name)| name).collect();
+                (name.clone(), suggestions)
+            })
+            .filter(|(_, suggestions): &(String, Vec<String>)| !suggestions.is_empty())
+            .collect()
+    }
+}
+
+fn format_many_device_names<T: Display>(names: &[T]) -> String {
     names
         .iter()
         .map(|name| format!("'{}'", name))
@@ -99,22 +378,33 @@ fn format_many_device_names(names: &Vec<String>) -> String {
 }
 
 #[cfg(test)]
+#[allow(non_snake_case)]
 mod test_DevicesConfig_extract_devices_to_remap {
     use super::*;
+    use crate::config::schema::{DeviceProperties, NameRegex};
     use crate::Key;
+    use std::path::PathBuf;
     extern crate testing_logger;
 
-    #[derive(Clone, Eq, PartialEq, Debug)]
+    #[derive(Clone, Eq, PartialEq, Debug, Default)]
     struct MockDevice {
         name: Option<String>,
         is_keyboard: bool,
+        /// The keys of a device which isn't a keyboard.
+        keys: Vec<Key>,
+        id: Option<(u16, u16)>,
+        phys: Option<String>,
+        uniq: Option<String>,
+        path: Option<PathBuf>,
     }
 
     impl MockDevice {
+        #[allow(dead_code)]
         pub fn new(name: &str, is_keyboard: bool) -> MockDevice {
             MockDevice {
                 name: Some(name.to_owned()),
                 is_keyboard,
+                ..Default::default()
             }
         }
     }
@@ -150,9 +440,14 @@ mod test_DevicesConfig_extract_devices_to_remap {
         type Iter<'a> = VecIterator<Key>;
         fn supported_keys(&self) -> Result<Self::Iter<'_>, DeviceError> {
             if self.is_keyboard {
-                Ok(VecIterator::new(vec![Key::KEY_ENTER]))
+                let letters = (Key::KEY_Q.code()..=Key::KEY_P.code())
+                    .chain(Key::KEY_A.code()..=Key::KEY_L.code())
+                    .chain(Key::KEY_Z.code()..=Key::KEY_M.code());
+                let mut keys: Vec<Key> = letters.map(Key::new).collect();
+                keys.push(Key::KEY_ENTER);
+                Ok(VecIterator::new(keys))
             } else {
-                Ok(VecIterator::new(vec![]))
+                Ok(VecIterator::new(self.keys.clone()))
             }
         }
 
@@ -162,17 +457,27 @@ mod test_DevicesConfig_extract_devices_to_remap {
                 Some(name) => Some(name.as_str()),
             }
         }
+
+        fn id(&self) -> Option<(u16, u16)> {
+            self.id
+        }
+
+        fn phys(&self) -> Option<&str> {
+            self.phys.as_deref()
+        }
+
+        fn uniq(&self) -> Option<&str> {
+            self.uniq.as_deref()
+        }
+
+        fn path(&self) -> Option<&Path> {
+            self.path.as_deref()
+        }
     }
 
-    impl ToString for MockDevice {
-        fn to_string(&self) -> String {
-            return format!(
-                "{}",
-                match self.name() {
-                    None => "UNNAMED",
-                    Some(name) => name,
-                }
-            );
+    impl fmt::Display for MockDevice {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.name().unwrap_or("UNNAMED"))
         }
     }
 
@@ -180,18 +485,22 @@ mod test_DevicesConfig_extract_devices_to_remap {
     struct NotKeyboard {}
 
     impl Keyboard {
+        #[allow(clippy::new_ret_no_self)]
         pub fn new(name: &str) -> MockDevice {
             MockDevice {
                 name: Some(name.to_owned()),
                 is_keyboard: true,
+                ..Default::default()
             }
         }
     }
     impl NotKeyboard {
+        #[allow(clippy::new_ret_no_self)]
         pub fn new(name: &str) -> MockDevice {
             MockDevice {
                 name: Some(name.to_owned()),
                 is_keyboard: false,
+                ..Default::default()
             }
         }
     }
@@ -226,6 +535,7 @@ mod test_DevicesConfig_extract_devices_to_remap {
                 DevicesConfig {
                     include: None,
                     exclude: None,
+                    classes: None,
                 },
                 mixed_devices(),
                 vec![
@@ -239,10 +549,8 @@ mod test_DevicesConfig_extract_devices_to_remap {
             check_selected_devs_are_expected(
                 DevicesConfig {
                     include: None,
-                    exclude: Some(vec![
-                        "special keyboard".to_owned(),
-                        "special device".to_owned(),
-                    ]),
+                    exclude: Some(vec!["special keyboard".into(), "special device".into()]),
+                    classes: None,
                 },
                 [
                     mixed_devices(),
@@ -268,11 +576,9 @@ mod test_DevicesConfig_extract_devices_to_remap {
         fn only_include_devices_selected() {
             check_selected_devs_are_expected(
                 DevicesConfig {
-                    include: Some(vec![
-                        "real device 2".to_owned(),
-                        "real keyboard 2".to_owned(),
-                    ]),
+                    include: Some(vec!["real device 2".into(), "real keyboard 2".into()]),
                     exclude: None,
+                    classes: None,
                 },
                 mixed_devices(),
                 vec![
@@ -286,15 +592,13 @@ mod test_DevicesConfig_extract_devices_to_remap {
         fn excluded_devices_are_not_selected_even_if_in_include_as_well() {
             check_selected_devs_are_expected(
                 DevicesConfig {
-                    include: Some(vec![
-                        "real device 2".to_owned(),
-                        "real keyboard 2".to_owned(),
-                    ]),
+                    include: Some(vec!["real device 2".into(), "real keyboard 2".into()]),
                     exclude: Some(vec![
-                        "special device".to_owned(),
-                        "special keyboard".to_owned(),
-                        "real keyboard 2".to_owned(), // also in the include
+                        "special device".into(),
+                        "special keyboard".into(),
+                        "real keyboard 2".into(), // also in the include
                     ]),
+                    classes: None,
                 },
                 [
                     mixed_devices(),
@@ -309,6 +613,422 @@ mod test_DevicesConfig_extract_devices_to_remap {
         }
     }
 
+    #[cfg(test)]
+    mod test_device_selection_by_properties {
+        use super::*;
+
+        fn include(properties: DeviceProperties) -> DevicesConfig {
+            DevicesConfig {
+                include: Some(vec![DeviceMatcher::Properties(properties)]),
+                exclude: None,
+                classes: None,
+            }
+        }
+
+        fn plugged_in_at(name: &str, phys: &str) -> MockDevice {
+            MockDevice {
+                phys: Some(phys.to_owned()),
+                ..Keyboard::new(name)
+            }
+        }
+
+        #[test]
+        fn devices_of_the_same_model_are_told_apart_by_phys() {
+            check_selected_devs_are_expected(
+                include(DeviceProperties {
+                    name: Some("keyboard".to_owned()),
+                    phys: Some("usb-2/input0".to_owned()),
+                    ..Default::default()
+                }),
+                vec![
+                    plugged_in_at("keyboard", "usb-1/input0"),
+                    plugged_in_at("keyboard", "usb-2/input0"),
+                ],
+                vec![plugged_in_at("keyboard", "usb-2/input0")],
+            )
+        }
+
+        #[test]
+        fn devices_are_matched_by_vendor_and_product_id() {
+            let receiver = MockDevice {
+                id: Some((0x046d, 0xc52b)),
+                ..Keyboard::new("receiver")
+            };
+            check_selected_devs_are_expected(
+                include(DeviceProperties {
+                    id: Some("046d:C52B".to_owned()),
+                    ..Default::default()
+                }),
+                vec![
+                    receiver.clone(),
+                    MockDevice {
+                        id: Some((0x046d, 0xc52c)),
+                        ..Keyboard::new("other receiver")
+                    },
+                    Keyboard::new("no id"),
+                ],
+                vec![receiver],
+            )
+        }
+
+        #[test]
+        fn devices_are_matched_by_serial_number() {
+            let keyboard = MockDevice {
+                uniq: Some("SN123".to_owned()),
+                ..Keyboard::new("keyboard")
+            };
+            check_selected_devs_are_expected(
+                include(DeviceProperties {
+                    uniq: Some("SN123".to_owned()),
+                    ..Default::default()
+                }),
+                vec![keyboard.clone(), Keyboard::new("keyboard")],
+                vec![keyboard],
+            )
+        }
+
+        #[test]
+        fn names_are_matched_by_glob_or_regex() {
+            let devices = vec![
+                Keyboard::new("Logitech K120"),
+                Keyboard::new("Logitech Mouse"),
+                Keyboard::new("Cherry K120"),
+            ];
+            check_selected_devs_are_expected(
+                include(DeviceProperties {
+                    name_glob: Some("Logitech K*".to_owned()),
+                    ..Default::default()
+                }),
+                devices.clone(),
+                vec![Keyboard::new("Logitech K120")],
+            );
+            check_selected_devs_are_expected(
+                include(DeviceProperties {
+                    name_regex: Some(NameRegex::new("K1[0-9]+$")),
+                    ..Default::default()
+                }),
+                devices,
+                vec![Keyboard::new("Logitech K120"), Keyboard::new("Cherry K120")],
+            );
+        }
+
+        #[test]
+        fn every_property_given_has_to_match() {
+            check_selected_devs_are_expected(
+                include(DeviceProperties {
+                    name_glob: Some("*K120".to_owned()),
+                    phys: Some("usb-1/input0".to_owned()),
+                    ..Default::default()
+                }),
+                vec![
+                    plugged_in_at("Logitech K120", "usb-1/input0"),
+                    plugged_in_at("Cherry K120", "usb-2/input0"),
+                    plugged_in_at("Logitech Mouse", "usb-1/input0"),
+                ],
+                vec![plugged_in_at("Logitech K120", "usb-1/input0")],
+            )
+        }
+
+        #[test]
+        fn devices_are_matched_through_links_to_their_path() {
+            let dir = std::env::temp_dir().join(format!("by-id-test-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let node = dir.join("event3");
+            let link = dir.join("usb-Logitech_K120-event-kbd");
+            fs::write(&node, "").unwrap();
+            let _ = fs::remove_file(&link);
+            std::os::unix::fs::symlink(&node, &link).unwrap();
+
+            let keyboard = MockDevice {
+                path: Some(node.clone()),
+                ..Keyboard::new("keyboard")
+            };
+            let other = MockDevice {
+                path: Some(dir.join("event4")),
+                ..Keyboard::new("keyboard")
+            };
+            check_selected_devs_are_expected(
+                include(DeviceProperties {
+                    path: Some(link),
+                    ..Default::default()
+                }),
+                vec![other, keyboard.clone()],
+                vec![keyboard],
+            );
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn excluded_properties_are_not_selected() {
+            check_selected_devs_are_expected(
+                DevicesConfig {
+                    include: None,
+                    exclude: Some(vec![DeviceMatcher::Properties(DeviceProperties {
+                        phys: Some("usb-1/input0".to_owned()),
+                        ..Default::default()
+                    })]),
+                    classes: None,
+                },
+                vec![
+                    plugged_in_at("keyboard", "usb-1/input0"),
+                    plugged_in_at("keyboard", "usb-2/input0"),
+                ],
+                vec![plugged_in_at("keyboard", "usb-2/input0")],
+            )
+        }
+    }
+
+    #[cfg(test)]
+    mod test_device_selection_by_class {
+        use super::*;
+
+        fn with_keys(name: &str, keys: &[Key]) -> MockDevice {
+            MockDevice {
+                keys: keys.to_vec(),
+                ..NotKeyboard::new(name)
+            }
+        }
+
+        fn devices() -> Vec<MockDevice> {
+            vec![
+                Keyboard::new("keyboard"),
+                with_keys("remote", &[Key::KEY_ENTER, Key::KEY_VOLUMEUP]),
+                with_keys("Power Button", &[Key::KEY_POWER]),
+                with_keys(
+                    "numpad",
+                    &[
+                        Key::KEY_KP0,
+                        Key::KEY_KP1,
+                        Key::KEY_KP2,
+                        Key::KEY_KP3,
+                        Key::KEY_KP4,
+                        Key::KEY_KP5,
+                        Key::KEY_KP6,
+                        Key::KEY_KP7,
+                        Key::KEY_KP8,
+                        Key::KEY_KP9,
+                        Key::KEY_KPENTER,
+                    ],
+                ),
+            ]
+        }
+
+        #[test]
+        fn only_full_keyboards_are_selected_by_default() {
+            check_selected_devs_are_expected(
+                DevicesConfig::default(),
+                devices(),
+                vec![Keyboard::new("keyboard")],
+            )
+        }
+
+        #[test]
+        fn other_classes_can_be_selected() {
+            let selected = DevicesConfig {
+                classes: Some(vec![DeviceClass::Keyboard, DeviceClass::Numpad]),
+                ..Default::default()
+            }
+            .extract_devices_to_remap(devices())
+            .unwrap();
+            assert_eq!(
+                selected
+                    .iter()
+                    .map(|dev| dev.to_string())
+                    .collect::<Vec<_>>(),
+                vec!["keyboard", "numpad"]
+            );
+        }
+
+        #[test]
+        fn included_devices_can_be_matched_by_class() {
+            check_selected_devs_are_expected(
+                DevicesConfig {
+                    include: Some(vec![DeviceMatcher::Properties(DeviceProperties {
+                        class: Some(DeviceClass::MediaKeys),
+                        ..Default::default()
+                    })]),
+                    ..Default::default()
+                },
+                devices(),
+                vec![with_keys("remote", &[Key::KEY_ENTER, Key::KEY_VOLUMEUP])],
+            )
+        }
+    }
+
+    #[cfg(test)]
+    mod test_selection {
+        use super::*;
+
+        fn check_selection_agrees(config: DevicesConfig, all_devs: Vec<MockDevice>) {
+            let selected = config.clone().extract_devices_to_remap(all_devs.clone());
+            let selected = selected.unwrap_or_default();
+            for device in &all_devs {
+                assert_eq!(
+                    config.selection(device).is_selected(),
+                    selected.contains(device),
+                    "{}: {}",
+                    device,
+                    config.selection(device)
+                );
+            }
+        }
+
+        #[test]
+        fn selection_agrees_with_the_devices_extracted() {
+            check_selection_agrees(DevicesConfig::default(), mixed_devices());
+            check_selection_agrees(
+                DevicesConfig {
+                    include: Some(vec!["real device 2".into(), "real keyboard 2".into()]),
+                    exclude: Some(vec!["real keyboard 2".into()]),
+                    classes: None,
+                },
+                mixed_devices(),
+            );
+            check_selection_agrees(
+                DevicesConfig {
+                    exclude: Some(vec!["real keyboard 1".into()]),
+                    ..Default::default()
+                },
+                mixed_devices(),
+            );
+            check_selection_agrees(
+                DevicesConfig {
+                    exclude: Some(vec!["real device 1".into()]),
+                    classes: Some(vec![DeviceClass::Keyboard, DeviceClass::Other]),
+                    ..Default::default()
+                },
+                mixed_devices(),
+            );
+        }
+
+        #[test]
+        fn selection_gives_the_rule_deciding_it() {
+            let config = DevicesConfig {
+                include: None,
+                exclude: Some(vec!["real keyboard 2".into()]),
+                classes: None,
+            };
+            assert_eq!(
+                config.selection(&Keyboard::new("real keyboard 1")),
+                Selection::OfClass(DeviceClass::Keyboard)
+            );
+            assert_eq!(
+                config.selection(&Keyboard::new("virtual keyboard")),
+                Selection::Virtual
+            );
+            assert_eq!(
+                config.selection(&NotKeyboard::new("real device 1")),
+                Selection::NotOfClass(DeviceClass::Other)
+            );
+            assert_eq!(
+                config.selection(&Keyboard::new("real keyboard 2")),
+                Selection::Excluded("real keyboard 2".into())
+            );
+            let config = DevicesConfig {
+                include: Some(vec!["real keyboard 1".into()]),
+                ..Default::default()
+            };
+            assert_eq!(
+                config.selection(&Keyboard::new("real keyboard 1")),
+                Selection::Included("real keyboard 1".into())
+            );
+            assert_eq!(
+                config.selection(&Keyboard::new("real keyboard 2")),
+                Selection::NotIncluded
+            );
+        }
+    }
+
+    #[cfg(test)]
+    mod test_near_misses {
+        use super::*;
+
+        #[test]
+        fn misspelt_names_suggest_the_closest_devices() {
+            let config = DevicesConfig {
+                include: Some(vec!["Real Keybaord 1".into(), "real keyboard 3".into()]),
+                ..Default::default()
+            };
+            let devices = [
+                Keyboard::new("real keyboard 1"),
+                Keyboard::new("real keyboard 3"),
+                Keyboard::new("virtual device"),
+            ];
+            assert_eq!(
+                config.near_misses(&devices),
+                vec![(
+                    "Real Keybaord 1".to_owned(),
+                    vec!["real keyboard 1".to_owned(), "real keyboard 3".to_owned()]
+                )]
+            );
+        }
+
+        #[test]
+        fn names_which_match_or_are_nothing_alike_suggest_nothing() {
+            let config = DevicesConfig {
+                include: Some(vec!["real keyboard 1".into(), "trackball".into()]),
+                ..Default::default()
+            };
+            assert!(config
+                .near_misses(&[Keyboard::new("real keyboard 1")])
+                .is_empty());
+        }
+    }
+
+    #[cfg(test)]
+    mod test_explain_selection {
+        use super::*;
+
+        #[test]
+        fn each_device_is_given_the_rule_deciding_it() {
+            let config = DevicesConfig {
+                include: Some(vec!["pedal".into(), "keyboard".into(), "Trackbal".into()]),
+                exclude: Some(vec!["keyboard".into()]),
+                classes: None,
+            };
+            let devices = [
+                NotKeyboard::new("pedal"),
+                Keyboard::new("keyboard"),
+                NotKeyboard::new("trackball"),
+                NotKeyboard::new("mouse"),
+            ];
+            assert_eq!(
+                config.explain_selection(&devices),
+                vec![
+                    "Device selection:",
+                    "  selected      'pedal': in the include list as 'pedal'",
+                    "  not selected  'keyboard': in the exclude list as 'keyboard'",
+                    "  not selected  'trackball': not in the include list",
+                    "  not selected  'mouse': not in the include list",
+                    "'Trackbal' in the include list matches no device, did you mean 'trackball'?",
+                ]
+            );
+        }
+
+        #[test]
+        fn no_devices_selected_says_how_to_explain_why() {
+            let config = DevicesConfig {
+                include: Some(vec!["trackball".into()]),
+                ..Default::default()
+            };
+            let devices = vec![Keyboard::new("real keyboard 1")];
+            match config.select_devices(devices.clone(), false) {
+                Err(DeviceError::DevicesNotFound(message)) => {
+                    assert!(message.ends_with(
+                        "Run with --explain to see why each device was or wasn't selected."
+                    ))
+                }
+                result => panic!("Expected DevicesNotFound, got {:?}", result),
+            }
+            match config.select_devices(devices, true) {
+                Err(DeviceError::DevicesNotFound(message)) => {
+                    assert!(!message.contains("--explain"))
+                }
+                result => panic!("Expected DevicesNotFound, got {:?}", result),
+            }
+        }
+    }
+
     #[cfg(test)]
     mod test_no_device_selected_gives_error {
 
@@ -319,6 +1039,7 @@ mod test_DevicesConfig_extract_devices_to_remap {
             let result = DevicesConfig {
                 include: None,
                 exclude: None,
+                classes: None,
             }
             .extract_devices_to_remap(Vec::<MockDevice>::new());
             assert!(result.is_err());
@@ -334,7 +1055,8 @@ mod test_DevicesConfig_extract_devices_to_remap {
         fn expected_error_and_message_when_no_devices_left_after_excluded() {
             let result = DevicesConfig {
                 include: None,
-                exclude: Some(vec!["real keyboard 2".to_owned()]),
+                exclude: Some(vec!["real keyboard 2".into()]),
+                classes: None,
             }
             .extract_devices_to_remap(vec![Keyboard::new("real keyboard 2")]);
             assert!(result.is_err());
@@ -345,6 +1067,30 @@ mod test_DevicesConfig_extract_devices_to_remap {
                 .to_string()
                 .contains("No devices left after filtering out excluded devices"));
         }
+
+        #[test]
+        fn expected_error_and_message_when_no_devices_were_selected_to_exclude() {
+            let err = DevicesConfig {
+                include: Some(vec!["trackball".into()]),
+                exclude: Some(vec!["real keyboard 1".into()]),
+                classes: None,
+            }
+            .extract_devices_to_remap(vec![Keyboard::new("real keyboard 1")])
+            .unwrap_err();
+            assert!(err
+                .to_string()
+                .contains("No devices found which match names: 'trackball'"));
+
+            let err = DevicesConfig::default()
+                .extract_devices_to_remap(vec![
+                    NotKeyboard::new("real device 1"),
+                    Keyboard::new("virtual keyboard"),
+                ])
+                .unwrap_err();
+            assert!(err
+                .to_string()
+                .contains("No non-virtual keyboards found in existing devices."));
+        }
     }
 
     #[test]
@@ -353,11 +1099,12 @@ mod test_DevicesConfig_extract_devices_to_remap {
 
         let result = DevicesConfig {
             include: Some(vec![
-                "real keyboard 1".to_owned(),
-                "not present keyboard".to_owned(),
-                "not present keyboard 2".to_owned(),
+                "real keyboard 1".into(),
+                "not present keyboard".into(),
+                "not present keyboard 2".into(),
             ]),
             exclude: None,
+            classes: None,
         }
         .extract_devices_to_remap(vec![Keyboard::new("real keyboard 1")]);
         assert!(result.is_ok());
@@ -378,9 +1125,10 @@ mod test_DevicesConfig_extract_devices_to_remap {
         let result = DevicesConfig {
             include: None,
             exclude: Some(vec![
-                "real keyboard 1".to_owned(),
-                "not present keyboard".to_owned(),
+                "real keyboard 1".into(),
+                "not present keyboard".into(),
             ]),
+            classes: None,
         }
         .extract_devices_to_remap(vec![
             Keyboard::new("real keyboard 1"),
@@ -389,3 +1137,290 @@ mod test_DevicesConfig_extract_devices_to_remap {
         assert!(result.is_ok());
     }
 }
+
+#[cfg(test)]
+mod test_config_round_trip {
+    use super::*;
+    use crate::Key;
+
+    fn round_trip(content: &str) -> (Config, String) {
+        let config = parse_config(content).unwrap();
+        let written = config_to_string(&config).unwrap();
+        assert_eq!(parse_config(&written).unwrap(), config);
+        (config, written)
+    }
+
+    #[test]
+    fn compactable_maps_are_written_compactly() {
+        let (_, written) = round_trip(
+            r#"
+[aliases]
+nav = "capslock"
+
+[mappings]
+maps = [
+    {input = ["KEY_S", "KEY_D"], output = ["KEY_UP"]},
+    {input = ["j", "k"], kind = "sequence", output = ["esc"]},
+    {input = ["nav", "t"], output = ["KEY_LEFTCTRL", "KEY_LEFTSHIFT", "KEY_T"]},
+]
+"#,
+        );
+        assert!(written.contains(r#""s+d" = "up""#));
+        assert!(written.contains(r#""j k" = "esc""#));
+        assert!(written.contains(r#""capslock+t" = "C-S-t""#));
+    }
+
+    #[test]
+    fn keys_shadowed_by_aliases_are_written_by_code() {
+        let (config, written) = round_trip(
+            r#"
+[aliases]
+s = "KEY_D"
+
+[mappings.maps]
+"KEY_S+s" = "up"
+"#,
+        );
+        assert_eq!(
+            config.mappings.unwrap().maps.unwrap()[0].input,
+            vec![Key::KEY_S, Key::KEY_D]
+        );
+        assert!(written.contains(r#"input = [31, "d"]"#));
+    }
+
+    #[test]
+    fn maps_with_unnamed_keys_are_written_as_a_list() {
+        let (config, _) = round_trip(
+            r#"
+[mappings]
+maps = [
+    {input = [766], output = ["KEY_UP"]},
+    {input = ["s", "d"], output = ["KEY_DOWN"]},
+]
+"#,
+        );
+        assert_eq!(config.mappings.unwrap().maps.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn maps_can_refer_to_fingers() {
+        let (config, written) = round_trip(
+            r#"
+[position.left_hand]
+index = ["f", "g"]
+middle = "d"
+
+[mappings.maps]
+"left.index+left.middle" = "esc"
+"#,
+        );
+        assert_eq!(
+            config.mappings.unwrap().maps.unwrap()[0].input,
+            vec![Key::KEY_F, Key::KEY_D]
+        );
+        assert!(written.contains(r#""left.index+left.middle" = "esc""#));
+    }
+
+    #[test]
+    fn fingers_are_written_back_in_lists_of_maps() {
+        let (_, written) = round_trip(
+            r#"
+[position.right_hand]
+index = "j"
+
+[mappings]
+maps = [
+    {input = ["right.index", "k"], output = [766]},
+]
+"#,
+        );
+        assert!(written.contains(r#"input = ["right.index", "k"]"#));
+    }
+
+    #[test]
+    fn rows_and_mirror_survive_round_trip() {
+        let (config, _) = round_trip(
+            r#"
+[position]
+rows = [["d", "f", "j", "k"]]
+
+[position.left_hand]
+index = "f"
+
+[position.right_hand]
+index = "j"
+
+[mirror]
+key = "space"
+"#,
+        );
+        assert_eq!(config.position.rows().len(), 1);
+        assert_eq!(config.mirror.unwrap().key, Key::KEY_SPACE);
+    }
+
+    #[test]
+    fn escape_chord_is_on_by_default() {
+        let (config, written) = round_trip("");
+        assert_eq!(
+            config.escape_chord,
+            vec![Key::KEY_ESC, Key::KEY_BACKSPACE, Key::KEY_ENTER]
+        );
+        assert!(!written.contains("escape_chord"));
+    }
+
+    #[test]
+    fn escape_chord_can_be_changed_or_turned_off() {
+        let (config, _) = round_trip(r#"escape_chord = "lctrl+rctrl""#);
+        assert_eq!(
+            config.escape_chord,
+            vec![Key::KEY_LEFTCTRL, Key::KEY_RIGHTCTRL]
+        );
+        let (config, _) = round_trip("escape_chord = []");
+        assert!(config.escape_chord.is_empty());
+    }
+
+    #[test]
+    fn escape_chord_must_be_a_chord_of_more_than_one_key() {
+        assert!(parse_config(r#"escape_chord = "j k""#).is_err());
+        assert!(parse_config(r#"escape_chord = "esc""#).is_err());
+    }
+
+    #[test]
+    fn chords_which_cant_be_played_are_rejected() {
+        let err = parse_config(
+            r#"
+[mappings.maps]
+"s+d+s" = "up"
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"The map "s+d+s" = "up" has the same key more than once"#
+        );
+        let err = parse_config(
+            r#"
+[mappings.maps]
+"a+s+d+f+g+h+j+k+l+semicolon+apostrophe" = "up"
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("has 11 keys"));
+        assert!(parse_config(
+            r#"
+[mappings.maps]
+"s s" = "up"
+"#
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn privileges_survive_round_trip() {
+        let (config, _) = round_trip(
+            r#"
+[privileges]
+user = "remapper"
+group = "input"
+"#,
+        );
+        let privileges = config.privileges.unwrap();
+        assert_eq!(privileges.user.as_deref(), Some("remapper"));
+        assert_eq!(privileges.group.as_deref(), Some("input"));
+        assert!(!privileges.seccomp);
+    }
+
+    #[test]
+    fn seccomp_is_only_accepted_where_supported() {
+        let result = parse_config("[privileges]\nseccomp = true");
+        assert_eq!(result.is_ok(), privileges::SECCOMP_SUPPORTED);
+        if privileges::SECCOMP_SUPPORTED {
+            assert!(
+                round_trip("[privileges]\nseccomp = true")
+                    .0
+                    .privileges
+                    .unwrap()
+                    .seccomp
+            );
+        }
+    }
+
+    #[test]
+    fn keys_are_only_logged_if_asked_for() {
+        assert!(!round_trip("").0.log_keys);
+        assert!(round_trip("log_keys = true").0.log_keys);
+    }
+
+    #[test]
+    fn example_config_round_trips() {
+        round_trip(include_str!("../../config.toml"));
+    }
+
+    #[test]
+    fn devices_survive_round_trip() {
+        round_trip(
+            r#"
+[devices]
+include = ["My Keyboard"]
+"#,
+        );
+    }
+
+    #[test]
+    fn device_properties_survive_round_trip() {
+        let (config, _) = round_trip(
+            r#"
+[devices]
+include = [
+    "My Keyboard",
+    {name_glob = "Logitech*", id = "046d:c52b"},
+    {path = "/dev/input/by-id/usb-Keyboard-event-kbd"},
+    {class = "numpad"},
+]
+exclude = [{phys = "usb-0000:00:14.0-2/input1"}]
+"#,
+        );
+        let include = config.devices.include.unwrap();
+        assert_eq!(include[0], DeviceMatcher::Name("My Keyboard".to_owned()));
+        assert!(matches!(&include[1], DeviceMatcher::Properties(properties)
+            if properties.id.as_deref() == Some("046d:c52b")));
+        assert!(matches!(&include[3], DeviceMatcher::Properties(properties)
+            if properties.class == Some(DeviceClass::Numpad)));
+    }
+
+    #[test]
+    fn device_classes_survive_round_trip() {
+        let (config, _) = round_trip(
+            r#"
+[devices]
+classes = ["keyboard", "foot_pedal"]
+"#,
+        );
+        assert_eq!(
+            config.devices.classes,
+            Some(vec![DeviceClass::Keyboard, DeviceClass::FootPedal])
+        );
+        assert!(parse_config("[devices]\nclasses = [\"toaster\"]").is_err());
+    }
+
+    #[test]
+    fn invalid_device_properties_are_rejected() {
+        assert!(parse_config("[devices]\ninclude = [{}]").is_err());
+        let err = parse_config(
+            r#"[devices]
+include = [{name_regex = "("}]"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("Invalid name_regex \"(\""));
+        assert!(parse_config(
+            r#"[devices]
+include = [{id = "logitech"}]"#
+        )
+        .is_err());
+        assert!(parse_config(
+            r#"[devices]
+include = [{colour = "red"}]"#
+        )
+        .is_err());
+    }
+}
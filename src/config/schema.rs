@@ -1,42 +1,230 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::device::DeviceClass;
 use crate::mapping::Map;
-use serde_derive::Deserialize;
+use crate::position::Positions;
+use crate::Key;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Config {
+    /// User defined names for keys, e.g. `nav = "capslock"`, usable anywhere a key is expected.
     #[serde(default)]
-    pub devices: DevicesConfig,
+    pub aliases: BTreeMap<String, String>,
+    /// The key each finger rests on, fingers can be used in place of keys, e.g. `"left.index"`,
+    /// so that maps can be shared between different physical layouts.
+    #[serde(default, skip_serializing_if = "Positions::is_empty")]
+    pub position: Positions,
     #[serde(default)]
+    pub devices: DevicesConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub mappings: Option<MappingsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home_row_mods: Option<HomeRowModsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<MirrorConfig>,
+    /// Pressing all of these keys together stops the remapper at once, whatever the maps do, so
+    /// a bad config can't leave the keyboard unusable. An empty list turns this off.
+    #[serde(
+        default = "default_escape_chord",
+        deserialize_with = "super::deserialize::deserialize_chord",
+        serialize_with = "super::serialize::serialize_keys",
+        skip_serializing_if = "is_default_escape_chord"
+    )]
+    pub escape_chord: Vec<Key>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privileges: Option<PrivilegesConfig>,
+    /// Write the keys typed in log messages, rather than only how many there were. Only for
+    /// debugging, as passwords end up in the logs too.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub log_keys: bool,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
 pub struct DevicesConfig {
-    #[serde(default = "empty")]
-    pub include: Option<Vec<String>>,
-    #[serde(default = "empty")]
-    pub exclude: Option<Vec<String>>,
+    #[serde(default = "empty", skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<DeviceMatcher>>,
+    #[serde(default = "empty", skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<DeviceMatcher>>,
+    /// The kinds of device selected when no devices are included by name, keyboards by default.
+    #[serde(default = "empty", skip_serializing_if = "Option::is_none")]
+    pub classes: Option<Vec<DeviceClass>>,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct MappingsConfig {
-    pub maps: Option<Vec<Map>>,
+/// Picks out devices to include or exclude, either by name, or by a table of properties which all
+/// have to match, e.g. to tell apart two keyboards of the same model by where they're plugged in.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum DeviceMatcher {
+    Name(String),
+    Properties(DeviceProperties),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceProperties {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// A pattern for the name, where "*" matches any text and "?" any one character.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_glob: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_regex: Option<NameRegex>,
+    /// The vendor and product IDs in hex, e.g. "046d:c52b".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Where the device is plugged in, e.g. "usb-0000:00:14.0-2/input0".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phys: Option<String>,
+    /// The device's serial number, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uniq: Option<String>,
+    /// The device node, or a link to it such as /dev/input/by-id/usb-...-event-kbd, which stays
+    /// the same when the device is plugged back in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// What kind of device it is, e.g. "numpad".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<DeviceClass>,
+}
+
+/// A regex for device names, compiled once as the config is read. A regex which doesn't compile
+/// keeps its error, for validating the config to report.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(from = "String", into = "String")]
+pub struct NameRegex {
+    source: String,
+    regex: Result<Regex, regex::Error>,
 }
 
-impl Default for DevicesConfig {
-    fn default() -> Self {
-        Self {
-            include: None,
-            exclude: None,
+impl NameRegex {
+    pub fn new(source: &str) -> Self {
+        NameRegex {
+            source: source.to_owned(),
+            regex: Regex::new(source),
         }
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn regex(&self) -> Result<&Regex, &regex::Error> {
+        self.regex.as_ref()
+    }
+}
+
+impl PartialEq for NameRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
 }
 
-impl Default for MappingsConfig {
-    fn default() -> Self {
-        Self { maps: None }
+impl From<String> for NameRegex {
+    fn from(source: String) -> Self {
+        NameRegex::new(&source)
     }
 }
 
+impl From<NameRegex> for String {
+    fn from(regex: NameRegex) -> Self {
+        regex.source
+    }
+}
+
+impl From<&str> for DeviceMatcher {
+    fn from(name: &str) -> Self {
+        DeviceMatcher::Name(name.to_owned())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MappingsConfig {
+    /// Either a list of `{input = [...], output = [...]}` tables, or a table in the compact
+    /// syntax, e.g. `"s+d" = "up"`.
+    #[serde(
+        default,
+        deserialize_with = "super::deserialize::deserialize_maps",
+        serialize_with = "super::serialize::serialize_maps",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub maps: Option<Vec<Map>>,
+    /// How long after the first key of a chord the rest of its keys can be pressed.
+    #[serde(default = "default_chord_timeout_ms")]
+    pub chord_timeout_ms: u64,
+    /// How long after each key of a sequence the next key can be pressed.
+    #[serde(default = "default_sequence_timeout_ms")]
+    pub sequence_timeout_ms: u64,
+}
+
+/// Keys which type as usual when tapped but act as a modifier when held. Which hand each key is
+/// on comes from the position table, and a key only acts as a modifier early if the next key
+/// pressed is on the opposite hand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HomeRowModsConfig {
+    /// How long a key has to be held on its own to act as a modifier.
+    pub tapping_term_ms: u64,
+    /// Each key with the modifier it acts as when held, e.g. `d = "lctrl"`.
+    pub modifiers: Vec<(Key, Key)>,
+}
+
+/// A key which mirrors the keyboard while held, so that each key types the key pressed by the
+/// same finger of the other hand, e.g. for typing one-handed. The pairs of keys come from the
+/// position table, matched up by finger and by `position.rows`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorConfig {
+    #[serde(
+        deserialize_with = "super::deserialize::deserialize_key",
+        serialize_with = "super::serialize::serialize_key"
+    )]
+    pub key: Key,
+    /// The key types as usual if released within this time without another key being pressed.
+    #[serde(default = "default_tapping_term_ms")]
+    pub tapping_term_ms: u64,
+}
+
+/// What to give up once the devices are open, if started as root. Only read at startup.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PrivilegesConfig {
+    /// The user to switch to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// The group to switch to, instead of the user's own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Allow only the system calls remapping needs, with seccomp.
+    #[serde(default)]
+    pub seccomp: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
 fn empty<T>() -> Option<T> {
     None
 }
+
+fn default_chord_timeout_ms() -> u64 {
+    50
+}
+
+fn default_sequence_timeout_ms() -> u64 {
+    500
+}
+
+pub fn default_tapping_term_ms() -> u64 {
+    200
+}
+
+pub fn default_escape_chord() -> Vec<Key> {
+    vec![Key::KEY_ESC, Key::KEY_BACKSPACE, Key::KEY_ENTER]
+}
+
+fn is_default_escape_chord(keys: &[Key]) -> bool {
+    keys == default_escape_chord()
+}
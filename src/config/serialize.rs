@@ -0,0 +1,155 @@
+// Implement Serialize for structs used elsewhere in the crate, so a loaded config can be
+// written back out.
+
+use serde::ser::{SerializeMap, SerializeStruct, Serializer};
+use serde::Serialize;
+
+use super::compact;
+use super::schema::HomeRowModsConfig;
+use crate::mapping::{FingerKey, InputKind, Map};
+use crate::position::{Hand, Positions};
+use crate::Key;
+
+/// Keys are written by name where they have one, otherwise by key code.
+fn key_value(key: Key) -> toml::Value {
+    match compact::key_name(key) {
+        Some(name) => toml::Value::String(name),
+        None => toml::Value::Integer(key.code().into()),
+    }
+}
+
+struct KeyList<'a>(&'a [Key]);
+
+impl Serialize for KeyList<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(|key| key_value(*key)))
+    }
+}
+
+/// The keys of a map, with those given by finger written as the finger.
+struct MapKeyList<'a>(&'a [Key], &'a [FingerKey]);
+
+impl Serialize for MapKeyList<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            self.0
+                .iter()
+                .map(|key| match compact::map_key_name(*key, self.1) {
+                    Some(name) => toml::Value::String(name),
+                    None => toml::Value::Integer(key.code().into()),
+                }),
+        )
+    }
+}
+
+struct HandPosition<'a>(&'a Positions, Hand);
+
+impl Serialize for HandPosition<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let HandPosition(positions, hand) = *self;
+        serializer.collect_map(
+            positions
+                .fingers()
+                .filter(|(other, _, _)| *other == hand)
+                .map(|(_, finger, keys)| match keys {
+                    [key] => (finger, key_value(*key)),
+                    keys => (
+                        finger,
+                        toml::Value::Array(keys.iter().map(|key| key_value(*key)).collect()),
+                    ),
+                }),
+        )
+    }
+}
+
+impl Serialize for Positions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut table = serializer.serialize_struct("Positions", 3)?;
+        if self.rows().is_empty() {
+            table.skip_field("rows")?;
+        } else {
+            let rows: Vec<KeyList> = self.rows().iter().map(|row| KeyList(row)).collect();
+            table.serialize_field("rows", &rows)?;
+        }
+        table.serialize_field("left_hand", &HandPosition(self, Hand::Left))?;
+        table.serialize_field("right_hand", &HandPosition(self, Hand::Right))?;
+        table.end()
+    }
+}
+
+impl Serialize for Map {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut table = serializer.serialize_struct("Map", 3)?;
+        table.serialize_field("input", &MapKeyList(&self.input, &self.fingers))?;
+        match self.kind {
+            InputKind::Chord => table.skip_field("kind")?,
+            kind => table.serialize_field("kind", &kind)?,
+        }
+        table.serialize_field("output", &MapKeyList(&self.output, &self.fingers))?;
+        table.end()
+    }
+}
+
+impl Serialize for HomeRowModsConfig {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let modifiers: toml::Table = self
+            .modifiers
+            .iter()
+            .map(|(key, modifier)| {
+                let key = compact::key_name(*key).unwrap_or_else(|| key.code().to_string());
+                (key, key_value(*modifier))
+            })
+            .collect();
+        let mut table = serializer.serialize_struct("HomeRowModsConfig", 2)?;
+        table.serialize_field("tapping_term_ms", &self.tapping_term_ms)?;
+        table.serialize_field("modifiers", &modifiers)?;
+        table.end()
+    }
+}
+
+/// A single key, for use with `#[serde(serialize_with)]`.
+pub fn serialize_key<S: Serializer>(key: &Key, serializer: S) -> Result<S::Ok, S::Error> {
+    key_value(*key).serialize(serializer)
+}
+
+pub fn serialize_keys<S: Serializer>(keys: &[Key], serializer: S) -> Result<S::Ok, S::Error> {
+    KeyList(keys).serialize(serializer)
+}
+
+/// Write `maps` in the compact syntax when every map can be expressed in it, otherwise as a list.
+pub fn serialize_maps<S: Serializer>(
+    maps: &Option<Vec<Map>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let maps = match maps {
+        None => return serializer.serialize_none(),
+        Some(maps) => maps,
+    };
+    let compact_maps: Option<Vec<(String, String)>> = maps
+        .iter()
+        .map(|map| {
+            Some((
+                compact::format_input(map)?,
+                compact::format_output(&map.output, &map.fingers)?,
+            ))
+        })
+        .collect();
+
+    match compact_maps {
+        Some(compact_maps) if has_unique_inputs(&compact_maps) => {
+            let mut table = serializer.serialize_map(Some(compact_maps.len()))?;
+            for (input, output) in &compact_maps {
+                table.serialize_entry(input, output)?;
+            }
+            table.end()
+        }
+        _ => serializer.collect_seq(maps),
+    }
+}
+
+fn has_unique_inputs(compact_maps: &[(String, String)]) -> bool {
+    let mut inputs: Vec<&String> = compact_maps.iter().map(|(input, _)| input).collect();
+    inputs.sort();
+    inputs.dedup();
+    inputs.len() == compact_maps.len()
+}
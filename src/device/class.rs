@@ -0,0 +1,352 @@
+// Telling what kind of device an input device is, from the keys and axes it supports and the
+// properties udev gave it. Plenty of devices which aren't keyboards report keys, e.g. remotes
+// have an enter key and power buttons are devices of their own, so only devices which udev says
+// are keyboards, or which have a full set of letters, are taken to be keyboards.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use nix::sys::stat::{major, minor};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::key::KeySet;
+use crate::Key;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceClass {
+    /// A keyboard, which udev says is one or which has every letter key.
+    Keyboard,
+    /// A number pad of its own, without letters.
+    Numpad,
+    /// Volume and playback keys, e.g. on a remote or a laptop's hotkeys.
+    MediaKeys,
+    /// The power or sleep button, which the kernel makes a device of its own.
+    PowerButton,
+    /// A mouse which also reports keys, e.g. a gaming mouse with programmable buttons.
+    MouseWithKeys,
+    /// A pedal or switch with a few keys, for pressing with the feet.
+    FootPedal,
+    /// Anything else, e.g. a plain mouse, touchpad or lid switch.
+    Other,
+}
+
+/// What a device can do, to classify it by.
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    pub name: String,
+    pub keys: KeySet,
+    /// Whether it moves a pointer, like a mouse or trackball.
+    pub pointer: bool,
+    /// The properties udev gave the device, e.g. ID_INPUT_KEYBOARD=1, if any were found.
+    pub udev: BTreeMap<String, String>,
+}
+
+const LETTERS: [Key; 26] = [
+    Key::KEY_A,
+    Key::KEY_B,
+    Key::KEY_C,
+    Key::KEY_D,
+    Key::KEY_E,
+    Key::KEY_F,
+    Key::KEY_G,
+    Key::KEY_H,
+    Key::KEY_I,
+    Key::KEY_J,
+    Key::KEY_K,
+    Key::KEY_L,
+    Key::KEY_M,
+    Key::KEY_N,
+    Key::KEY_O,
+    Key::KEY_P,
+    Key::KEY_Q,
+    Key::KEY_R,
+    Key::KEY_S,
+    Key::KEY_T,
+    Key::KEY_U,
+    Key::KEY_V,
+    Key::KEY_W,
+    Key::KEY_X,
+    Key::KEY_Y,
+    Key::KEY_Z,
+];
+
+const NUMPAD_DIGITS: [Key; 10] = [
+    Key::KEY_KP0,
+    Key::KEY_KP1,
+    Key::KEY_KP2,
+    Key::KEY_KP3,
+    Key::KEY_KP4,
+    Key::KEY_KP5,
+    Key::KEY_KP6,
+    Key::KEY_KP7,
+    Key::KEY_KP8,
+    Key::KEY_KP9,
+];
+
+const POWER_KEYS: [Key; 5] = [
+    Key::KEY_POWER,
+    Key::KEY_POWER2,
+    Key::KEY_SLEEP,
+    Key::KEY_SUSPEND,
+    Key::KEY_WAKEUP,
+];
+
+const MEDIA_KEYS: [Key; 14] = [
+    Key::KEY_MUTE,
+    Key::KEY_VOLUMEDOWN,
+    Key::KEY_VOLUMEUP,
+    Key::KEY_PLAYPAUSE,
+    Key::KEY_PLAYCD,
+    Key::KEY_PAUSECD,
+    Key::KEY_STOPCD,
+    Key::KEY_NEXTSONG,
+    Key::KEY_PREVIOUSSONG,
+    Key::KEY_FASTFORWARD,
+    Key::KEY_REWIND,
+    Key::KEY_MEDIA,
+    Key::KEY_BRIGHTNESSDOWN,
+    Key::KEY_BRIGHTNESSUP,
+];
+
+/// Most pedals have no more keys than this.
+const MAX_PEDAL_KEYS: usize = 4;
+
+/// Buses of devices which are plugged in, rather than built in like a laptop's hotkeys.
+const PLUGGED_IN_BUSES: [&str; 2] = ["usb", "bluetooth"];
+
+/// Whether `key` is a mouse, joystick or other button rather than a key, which the kernel gives
+/// codes of their own.
+fn is_button(key: Key) -> bool {
+    let code = key.code();
+    (Key::BTN_0.code()..Key::KEY_OK.code()).contains(&code)
+        || code >= Key::BTN_TRIGGER_HAPPY1.code()
+}
+
+pub fn classify(capabilities: &Capabilities) -> DeviceClass {
+    let udev_says = |property: &str| capabilities.udev.get(property).is_some_and(|v| v == "1");
+    if [
+        "ID_INPUT_JOYSTICK",
+        "ID_INPUT_TABLET",
+        "ID_INPUT_TOUCHSCREEN",
+    ]
+    .iter()
+    .any(|property| udev_says(property))
+    {
+        return DeviceClass::Other;
+    }
+    let name = capabilities.name.to_lowercase();
+    if ["pedal", "footswitch", "foot switch"]
+        .iter()
+        .any(|hint| name.contains(hint))
+    {
+        return DeviceClass::FootPedal;
+    }
+
+    let keys: KeySet = capabilities
+        .keys
+        .iter()
+        .filter(|key| !is_button(*key))
+        .collect();
+    let pointer = capabilities.pointer
+        || [
+            "ID_INPUT_MOUSE",
+            "ID_INPUT_TOUCHPAD",
+            "ID_INPUT_POINTINGSTICK",
+        ]
+        .iter()
+        .any(|property| udev_says(property));
+    let plugged_in = capabilities
+        .udev
+        .get("ID_BUS")
+        .is_some_and(|bus| PLUGGED_IN_BUSES.contains(&bus.as_str()));
+
+    if udev_says("ID_INPUT_KEYBOARD") || LETTERS.iter().all(|key| keys.contains(*key)) {
+        DeviceClass::Keyboard
+    } else if pointer && !keys.is_empty() {
+        DeviceClass::MouseWithKeys
+    } else if pointer || keys.is_empty() {
+        DeviceClass::Other
+    } else if keys.iter().all(|key| POWER_KEYS.contains(&key)) {
+        DeviceClass::PowerButton
+    } else if NUMPAD_DIGITS.iter().all(|key| keys.contains(*key)) {
+        DeviceClass::Numpad
+    } else if MEDIA_KEYS.iter().any(|key| keys.contains(*key)) {
+        DeviceClass::MediaKeys
+    } else if keys.len() <= MAX_PEDAL_KEYS && udev_says("ID_INPUT_KEY") && plugged_in {
+        // Devices built in with a few keys, e.g. "Intel HID events", aren't pedals.
+        DeviceClass::FootPedal
+    } else {
+        DeviceClass::Other
+    }
+}
+
+/// The properties udev gave the device node at `path`, from its database in /run/udev/data.
+pub fn udev_properties(path: &Path) -> BTreeMap<String, String> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return BTreeMap::new(),
+    };
+    let rdev = metadata.rdev();
+    let data = format!("/run/udev/data/c{}:{}", major(rdev), minor(rdev));
+    fs::read_to_string(data)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.strip_prefix("E:")?.split_once('='))
+        .map(|(property, value)| (property.to_owned(), value.to_owned()))
+        .collect()
+}
+
+impl fmt::Display for DeviceClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DeviceClass::Keyboard => "keyboard",
+            DeviceClass::Numpad => "numpad",
+            DeviceClass::MediaKeys => "media_keys",
+            DeviceClass::PowerButton => "power_button",
+            DeviceClass::MouseWithKeys => "mouse_with_keys",
+            DeviceClass::FootPedal => "foot_pedal",
+            DeviceClass::Other => "other",
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_classify {
+    use super::*;
+
+    fn device(name: &str, keys: &[Key]) -> Capabilities {
+        Capabilities {
+            name: name.to_owned(),
+            keys: keys.iter().copied().collect(),
+            ..Default::default()
+        }
+    }
+
+    fn keyboard_keys() -> Vec<Key> {
+        [
+            &LETTERS[..],
+            &[Key::KEY_ENTER, Key::KEY_SPACE, Key::KEY_ESC],
+        ]
+        .concat()
+    }
+
+    fn with_udev(capabilities: Capabilities, properties: &[(&str, &str)]) -> Capabilities {
+        Capabilities {
+            udev: properties
+                .iter()
+                .map(|(property, value)| (property.to_string(), value.to_string()))
+                .collect(),
+            ..capabilities
+        }
+    }
+
+    #[test]
+    fn devices_with_every_letter_are_keyboards() {
+        assert_eq!(
+            classify(&device("AT keyboard", &keyboard_keys())),
+            DeviceClass::Keyboard
+        );
+    }
+
+    #[test]
+    fn devices_udev_says_are_keyboards_are_keyboards() {
+        let keyboard = with_udev(
+            device("Keyboard", &LETTERS[..20]),
+            &[("ID_INPUT_KEY", "1"), ("ID_INPUT_KEYBOARD", "1")],
+        );
+        assert_eq!(classify(&keyboard), DeviceClass::Keyboard);
+    }
+
+    #[test]
+    fn remotes_with_enter_are_not_keyboards() {
+        let remote = device(
+            "remote",
+            &[
+                Key::KEY_ENTER,
+                Key::KEY_UP,
+                Key::KEY_DOWN,
+                Key::KEY_VOLUMEUP,
+            ],
+        );
+        assert_eq!(classify(&remote), DeviceClass::MediaKeys);
+    }
+
+    #[test]
+    fn numpads_without_enter_are_numpads() {
+        let numpad = device(
+            "numpad",
+            &[&NUMPAD_DIGITS[..], &[Key::KEY_NUMLOCK]].concat(),
+        );
+        assert_eq!(classify(&numpad), DeviceClass::Numpad);
+    }
+
+    #[test]
+    fn power_buttons_only_have_power_keys() {
+        assert_eq!(
+            classify(&device("Power Button", &[Key::KEY_POWER])),
+            DeviceClass::PowerButton
+        );
+        assert_eq!(
+            classify(&device("Sleep Button", &[Key::KEY_SLEEP])),
+            DeviceClass::PowerButton
+        );
+    }
+
+    #[test]
+    fn mice_are_only_classed_by_their_keys() {
+        let mouse = Capabilities {
+            pointer: true,
+            ..device("mouse", &[Key::BTN_LEFT, Key::BTN_RIGHT])
+        };
+        assert_eq!(classify(&mouse), DeviceClass::Other);
+        let gaming_mouse = Capabilities {
+            pointer: true,
+            ..device("gaming mouse", &[Key::BTN_LEFT, Key::KEY_1, Key::KEY_2])
+        };
+        assert_eq!(classify(&gaming_mouse), DeviceClass::MouseWithKeys);
+    }
+
+    #[test]
+    fn udev_can_tell_of_pointers() {
+        let trackball = with_udev(
+            device("trackball", &[Key::BTN_LEFT, Key::KEY_BACK]),
+            &[("ID_INPUT_MOUSE", "1")],
+        );
+        assert_eq!(classify(&trackball), DeviceClass::MouseWithKeys);
+    }
+
+    #[test]
+    fn plugged_in_devices_with_a_few_keys_are_pedals() {
+        let keys = [Key::KEY_A, Key::KEY_B, Key::KEY_C];
+        assert_eq!(
+            classify(&with_udev(
+                device("USB device", &keys),
+                &[("ID_INPUT_KEY", "1"), ("ID_BUS", "usb")]
+            )),
+            DeviceClass::FootPedal
+        );
+        assert_eq!(classify(&device("USB device", &keys)), DeviceClass::Other);
+        assert_eq!(
+            classify(&device("PCsensor FootSwitch", &keyboard_keys())),
+            DeviceClass::FootPedal
+        );
+    }
+
+    #[test]
+    fn built_in_devices_with_a_few_keys_are_other() {
+        let hotkeys = with_udev(
+            device("Intel HID events", &[Key::KEY_LEFTMETA, Key::KEY_PROG1]),
+            &[("ID_INPUT_KEY", "1")],
+        );
+        assert_eq!(classify(&hotkeys), DeviceClass::Other);
+    }
+
+    #[test]
+    fn devices_without_keys_are_other() {
+        assert_eq!(classify(&device("Lid Switch", &[])), DeviceClass::Other);
+    }
+}
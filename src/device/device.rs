@@ -1,12 +1,15 @@
+use super::class::{classify, udev_properties, Capabilities, DeviceClass};
 use crate::errors::{DeviceError, VirtualDeviceCreationError};
-use crate::key::Key;
-use evdev::{AttributeSet, AttributeSetRef};
-use log;
-use std::{io, path::PathBuf};
+use crate::key::{Key, KeySet, KeyState, KEY_CODE_COUNT};
+use evdev::{AttributeSet, EventType, InputEvent, RelativeAxisCode};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 // Structs which wrap structs provided by another device interface library, currently evdev, but
 // this library could be changed if compiling for a different OS, or if another library is later preferred.
 
-pub struct Device(pub evdev::Device);
+/// A device, with the device node it was opened from.
+pub struct Device(pub evdev::Device, PathBuf);
 
 pub struct VirtualDevice(pub evdev::uinput::VirtualDevice);
 
@@ -16,8 +19,41 @@ pub trait DeviceInfo: ToString {
         Self: 'a;
     fn supported_keys<'a>(&'a self) -> Result<Self::Iter<'a>, DeviceError>;
     fn name(&self) -> Option<&str>;
+
+    /// The vendor and product IDs, e.g. of the USB device.
+    fn id(&self) -> Option<(u16, u16)> {
+        None
+    }
+
+    /// Where the device is plugged in, e.g. "usb-0000:00:14.0-2/input0".
+    fn phys(&self) -> Option<&str> {
+        None
+    }
+
+    /// A serial number or other unique identifier, which few devices have.
+    fn uniq(&self) -> Option<&str> {
+        None
+    }
+
+    /// The device node, e.g. /dev/input/event3.
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// What kind of device it is, going by its keys unless more is known.
+    fn class(&self) -> DeviceClass {
+        classify(&Capabilities {
+            name: self.name().unwrap_or_default().to_owned(),
+            keys: self
+                .supported_keys()
+                .map(|keys| keys.collect())
+                .unwrap_or_default(),
+            ..Default::default()
+        })
+    }
 }
 
+#[allow(dead_code)]
 pub trait VirtualDeviceInfo {
     fn enumerate_dev_nodes(
         &mut self,
@@ -26,25 +62,56 @@ pub trait VirtualDeviceInfo {
 
 impl Device {
     #[inline]
-    pub const fn new(device: evdev::Device) -> Self {
-        Self(device)
+    pub const fn new(device: evdev::Device, path: PathBuf) -> Self {
+        Self(device, path)
+    }
+
+    /// The keys held down on the device right now, as known to the kernel.
+    pub fn held_keys(&self) -> Result<KeySet, DeviceError> {
+        Ok(self.0.get_key_state()?.iter().collect())
+    }
+
+    /// Whether another program has grabbed the device, found by grabbing it for a moment.
+    pub fn grabbed_elsewhere(&mut self) -> Result<bool, DeviceError> {
+        match self.0.grab() {
+            Ok(()) => {
+                self.0.ungrab()?;
+                Ok(false)
+            }
+            Err(err) if err.raw_os_error() == Some(nix::libc::EBUSY) => Ok(true),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Make every program reading the device see `keys` released, by writing the releases to the
+    /// device itself, which the kernel passes on to all of its readers.
+    pub fn release_keys(&mut self, keys: &KeySet) -> Result<(), DeviceError> {
+        let mut events: Vec<InputEvent> = keys
+            .iter()
+            .map(|key| InputEvent::new(EventType::KEY.0, key.code(), KeyState::Released.value()))
+            .collect();
+        events.push(InputEvent::new(EventType::SYNCHRONIZATION.0, 0, 0));
+        self.0.send_events(&events)?;
+        Ok(())
     }
 }
 
 impl VirtualDevice {
+    #[allow(dead_code)]
     #[inline]
     pub const fn new(virtual_device: evdev::uinput::VirtualDevice) -> Self {
         Self(virtual_device)
     }
 
+    #[allow(dead_code)]
     pub fn from_template_device<T: DeviceInfo>(
         name: &str,
         template_device: &mut T,
     ) -> Result<VirtualDevice, VirtualDeviceCreationError> {
-        let keys = AttributeSet::<evdev::Key>::from_iter(template_device.supported_keys()?);
+        let keys = AttributeSet::<Key>::from_iter(template_device.supported_keys()?);
 
         let mut device = VirtualDevice(
-            evdev::uinput::VirtualDeviceBuilder::new()?
+            evdev::uinput::VirtualDevice::builder()?
                 .name(name)
                 .with_keys(&keys)?
                 .build()?,
@@ -54,38 +121,74 @@ impl VirtualDevice {
             let path = path?;
             println!("Virtual device available as {}", path.display());
         }
-        return Ok(device);
+        Ok(device)
+    }
+
+    /// A virtual device able to write any key, as remapped keys needn't be on the keyboard.
+    pub fn with_all_keys(name: &str) -> Result<VirtualDevice, VirtualDeviceCreationError> {
+        let keys = AttributeSet::<Key>::from_iter((0..KEY_CODE_COUNT).map(Key::new));
+        Ok(VirtualDevice(
+            evdev::uinput::VirtualDevice::builder()?
+                .name(name)
+                .with_keys(&keys)?
+                .build()?,
+        ))
     }
 }
 
-impl ToString for Device {
-    fn to_string(&self) -> String {
-        return format!(
-            "{}",
-            match self.name() {
-                None => "UNNAMED",
-                Some(name) => name,
-            }
-        );
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name().unwrap_or("UNNAMED"))
     }
 }
 
 impl DeviceInfo for Device {
     type Iter<'a> = Box<dyn Iterator<Item = Key> + 'a>;
     fn supported_keys<'a>(&'a self) -> Result<Self::Iter<'a>, DeviceError> {
-        return Ok(match self.0.supported_keys() {
-            Some(evdev_keys) => Box::new(evdev_keys.iter()),
-            None => {
-                return Err(DeviceError::SupportedKeysEmpty(format!(
-                    "No supported keys found on template device: {:?}",
-                    self.name()
-                )))
-            }
-        });
+        match self.0.supported_keys() {
+            Some(evdev_keys) => Ok(Box::new(evdev_keys.iter())),
+            None => Err(DeviceError::SupportedKeysEmpty(format!(
+                "No supported keys found on template device: {:?}",
+                self.name()
+            ))),
+        }
     }
 
     fn name(&self) -> Option<&str> {
-        return self.0.name();
+        self.0.name()
+    }
+
+    fn id(&self) -> Option<(u16, u16)> {
+        let id = self.0.input_id();
+        Some((id.vendor(), id.product()))
+    }
+
+    fn phys(&self) -> Option<&str> {
+        self.0.physical_path()
+    }
+
+    fn uniq(&self) -> Option<&str> {
+        self.0.unique_name().filter(|uniq| !uniq.is_empty())
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.1)
+    }
+
+    fn class(&self) -> DeviceClass {
+        let pointer = self.0.supported_relative_axes().is_some_and(|axes| {
+            axes.contains(RelativeAxisCode::REL_X) && axes.contains(RelativeAxisCode::REL_Y)
+        });
+        classify(&Capabilities {
+            name: self.name().unwrap_or_default().to_owned(),
+            keys: self
+                .0
+                .supported_keys()
+                .map(|keys| keys.iter().collect())
+                .unwrap_or_default(),
+            pointer,
+            udev: udev_properties(&self.1),
+        })
     }
 }
 
@@ -100,22 +203,27 @@ impl VirtualDeviceInfo for VirtualDevice {
     }
 }
 
-fn enumerate_devices() -> Box<dyn Iterator<Item = (PathBuf, Device)>> {
-    Box::new(
-        evdev::enumerate()
-            .into_iter()
-            .map(|(path, device)| (path, Device::new(device))),
-    )
+fn enumerate_devices() -> Box<dyn Iterator<Item = Device>> {
+    Box::new(evdev::enumerate().map(|(path, device)| Device::new(device, path)))
+}
+
+/// Find the device called `name` and plugged in at `phys` again, e.g. after it was unplugged,
+/// other than any at the paths `skip`.
+pub fn find_device(name: Option<&str>, phys: Option<&str>, skip: &[&Path]) -> Option<Device> {
+    enumerate_devices().find(|device| {
+        device.name() == name
+            && device.phys() == phys
+            && !device.path().is_some_and(|path| skip.contains(&path))
+    })
 }
 
 pub fn get_all_devices() -> Result<Vec<Device>, DeviceError> {
-    let devices = enumerate_devices()
-        .map(|(_, device)| device)
-        .collect::<Vec<Device>>();
+    let devices = enumerate_devices().collect::<Vec<Device>>();
     match devices.len() {
-        0 => Err(DeviceError::DevicesNotFound(format!(
-            "No devices found, make sure the program is running under sudo privileges."
-        ))),
+        0 => Err(DeviceError::DevicesNotFound(
+            "No devices found, run `chorded-key-remapper doctor` to check they can be accessed."
+                .to_owned(),
+        )),
         _ => Ok(devices),
     }
 }
@@ -0,0 +1,667 @@
+// The event loop reading the grabbed devices. A single thread waits with epoll on every device,
+// a timer for the engine's next deadline, signals and changes to the config file, so the engine
+// has exactly one owner and events from different devices are handled in the order they
+// happened.
+
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::os::raw::c_int;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use evdev::{EventSummary, InputEvent, SynchronizationCode};
+use nix::sys::epoll::{
+    epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+
+use super::device::{find_device, Device, DeviceInfo};
+use super::events::{EventSource, Poll};
+use super::opener::DeviceOpener;
+use crate::clock::{Clock, MonotonicClock};
+use crate::errors::DeviceError;
+use crate::key::{KeyEvent, KeySet, KeyState};
+use crate::logging::Keys;
+
+// What each file descriptor registered with epoll is, devices being identified by their index.
+const TIMER: u64 = u64::MAX;
+const SIGNALS: u64 = u64::MAX - 1;
+const CONFIG: u64 = u64::MAX - 2;
+
+/// How long to wait for keys held down at grab time to be released, e.g. the Enter which started
+/// the remapper from a terminal.
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(2);
+const RELEASE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long after a device first fails to try reopening it, and the longest between retries.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Most events read from a device at once.
+const READ_BATCH: usize = 64;
+
+/// Epoll instance, closed on drop.
+struct Epoll(RawFd);
+
+impl Epoll {
+    fn new() -> Result<Epoll, DeviceError> {
+        Ok(Epoll(
+            epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC).map_err(std::io::Error::from)?,
+        ))
+    }
+
+    fn add(&self, fd: RawFd, flags: EpollFlags, token: u64) -> Result<(), DeviceError> {
+        let mut event = EpollEvent::new(flags, token);
+        epoll_ctl(self.0, EpollOp::EpollCtlAdd, fd, &mut event).map_err(std::io::Error::from)?;
+        Ok(())
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.0);
+    }
+}
+
+/// Watches the directory of the config file, as editors often replace the file rather than
+/// writing to it.
+struct ConfigWatcher {
+    inotify: Inotify,
+    file_name: OsString,
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.inotify.as_raw_fd());
+    }
+}
+
+nix::ioctl_write_ptr!(eviocsclockid, b'E', 0xa0, c_int);
+
+/// Have the kernel timestamp the events of `device` with CLOCK_MONOTONIC.
+fn use_monotonic_timestamps(device: &Device) -> Result<(), DeviceError> {
+    let clock_id: c_int = nix::libc::CLOCK_MONOTONIC;
+    unsafe { eviocsclockid(device.0.as_raw_fd(), &clock_id) }.map_err(std::io::Error::from)?;
+    Ok(())
+}
+
+/// Wait until `held_keys` returns no keys or `timeout` has passed on `clock`, sleeping between
+/// checks, returning the keys still held.
+fn wait_for_release(
+    clock: &impl Clock,
+    timeout: Duration,
+    mut held_keys: impl FnMut() -> Result<KeySet, DeviceError>,
+    mut sleep: impl FnMut(Duration),
+) -> Result<KeySet, DeviceError> {
+    let deadline = clock.now() + timeout;
+    loop {
+        let held = held_keys()?;
+        if held.is_empty() || clock.now() >= deadline {
+            return Ok(held);
+        }
+        sleep(RELEASE_POLL_INTERVAL);
+    }
+}
+
+/// How long after a device fails to try reopening it, doubling with each failure in a row.
+fn retry_delay(failures: u32) -> Duration {
+    RETRY_DELAY
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Why a device failed, in words, for the common reasons.
+fn describe_failure(err: &DeviceError) -> String {
+    let reason = match err {
+        DeviceError::IO(err) => match err.raw_os_error() {
+            Some(nix::libc::EACCES | nix::libc::EPERM) => Some("permission denied"),
+            Some(nix::libc::EBUSY) => Some("busy, probably grabbed by another program"),
+            Some(nix::libc::ENODEV | nix::libc::ENOENT) => Some("disconnected"),
+            _ => None,
+        },
+        _ => None,
+    };
+    match reason {
+        Some(reason) => format!("{} ({})", reason, err),
+        None => err.to_string(),
+    }
+}
+
+/// Take exclusive access to `device`, first releasing any keys held down on it for the other
+/// programs reading it.
+fn grab_device(device: &mut Device) -> Result<(), DeviceError> {
+    let held = device.held_keys()?;
+    if !held.is_empty() {
+        log::warn!(
+            "Releasing {}, still held down on '{}'",
+            Keys(held.iter().collect()),
+            device.to_string()
+        );
+        device.release_keys(&held)?;
+    }
+    device.0.grab()?;
+    Ok(())
+}
+
+/// One of the devices read. A device which fails, e.g. by being unplugged, is closed, and found
+/// again by its name and where it's plugged in to be reopened.
+struct Slot {
+    device: Option<Device>,
+    name: Option<String>,
+    phys: Option<String>,
+    path: PathBuf,
+    /// Whether the kernel dropped events, which are skipped until the end of the dropped report.
+    dropping: bool,
+    /// How many times in a row the device has failed.
+    failures: u32,
+    retry_at: Duration,
+}
+
+impl Slot {
+    fn new(device: &Device) -> Slot {
+        Slot {
+            device: None,
+            name: device.name().map(str::to_owned),
+            phys: device.phys().map(str::to_owned),
+            path: device.path().map(Path::to_owned).unwrap_or_default(),
+            dropping: false,
+            failures: 0,
+            retry_at: Duration::ZERO,
+        }
+    }
+
+    fn label(&self) -> String {
+        format!(
+            "'{}' ({})",
+            self.name.as_deref().unwrap_or("UNNAMED"),
+            self.path.display()
+        )
+    }
+}
+
+/// Reads key events from evdev devices, timed by the kernel when they happened rather than when
+/// they're read, as events can be read in bursts when the machine is busy.
+///
+/// A device failing doesn't stop the others being read: it's reported, and retried with
+/// backoff until it can be reopened.
+///
+/// SIGINT and SIGTERM close the source, and SIGHUP is taken as a change to the config.
+pub struct EvdevSource {
+    slots: Vec<Slot>,
+    /// When the devices were grabbed. Events from before then were seen by other programs too,
+    /// so are ignored.
+    grabbed_at: Option<Duration>,
+    /// Events read but not yet returned, as devices return events in batches.
+    pending: VecDeque<KeyEvent>,
+    /// Whether the keys held down need reporting with `Poll::Resync`.
+    resync: bool,
+    clock: MonotonicClock,
+    epoll: Epoll,
+    timer: TimerFd,
+    signals: SignalFd,
+    config: Option<ConfigWatcher>,
+    /// Opens devices to reopen once root has been given up.
+    opener: Option<DeviceOpener>,
+}
+
+impl EvdevSource {
+    pub fn new(devices: Vec<Device>) -> Result<EvdevSource, DeviceError> {
+        let epoll = Epoll::new()?;
+
+        // Timers are on the clock devices timestamp events with, so deadlines can be set
+        // directly from event times.
+        let timer = TimerFd::new(
+            ClockId::CLOCK_MONOTONIC,
+            TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC,
+        )
+        .map_err(std::io::Error::from)?;
+        epoll.add(timer.as_raw_fd(), EpollFlags::EPOLLIN, TIMER)?;
+
+        // Signals are blocked so that they're only delivered through the signalfd.
+        let mut mask = SigSet::empty();
+        mask.add(Signal::SIGINT);
+        mask.add(Signal::SIGTERM);
+        mask.add(Signal::SIGHUP);
+        mask.thread_block().map_err(std::io::Error::from)?;
+        let signals = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)
+            .map_err(std::io::Error::from)?;
+        epoll.add(signals.as_raw_fd(), EpollFlags::EPOLLIN, SIGNALS)?;
+
+        let mut source = EvdevSource {
+            slots: devices.iter().map(Slot::new).collect(),
+            grabbed_at: None,
+            pending: VecDeque::new(),
+            resync: false,
+            clock: MonotonicClock,
+            epoll,
+            timer,
+            signals,
+            config: None,
+            opener: None,
+        };
+        for (index, device) in devices.into_iter().enumerate() {
+            if let Err(err) = source.open(index, device) {
+                source.fail(index, err);
+            }
+        }
+        Ok(source)
+    }
+
+    /// Take exclusive access to the devices, so that only the remapped events reach other
+    /// programs. They're released again when the source is dropped.
+    ///
+    /// A key grabbed while held down would never be seen released by other programs, so the
+    /// devices are only grabbed once all their keys are released. Any still held after a timeout
+    /// are released for the other programs before grabbing.
+    ///
+    /// Devices which can't be grabbed are retried later, so this only fails if none can be.
+    pub fn grab(&mut self) -> Result<(), DeviceError> {
+        if !self.held_keys().is_empty() {
+            println!("Waiting for keys to be released before grabbing the devices");
+        }
+        wait_for_release(
+            &self.clock,
+            RELEASE_TIMEOUT,
+            || Ok(self.held_keys()),
+            std::thread::sleep,
+        )?;
+
+        for index in 0..self.slots.len() {
+            if let Some(device) = &mut self.slots[index].device {
+                if let Err(err) = grab_device(device) {
+                    self.fail(index, err);
+                }
+            }
+        }
+        if !self.slots.is_empty() && self.slots.iter().all(|slot| slot.device.is_none()) {
+            return Err(DeviceError::Message(
+                "None of the selected devices could be grabbed".to_owned(),
+            ));
+        }
+        self.grabbed_at = Some(self.clock.now());
+        // Keys pressed while waiting, or released by the timeout, are only known from the
+        // devices' state.
+        self.resync = true;
+        Ok(())
+    }
+
+    /// Reopen devices through `opener` rather than opening them directly, which fails once root
+    /// has been given up.
+    pub fn reopen_through(&mut self, opener: DeviceOpener) {
+        self.opener = Some(opener);
+    }
+
+    /// Report the keys held down on the next poll, e.g. once the engine has been rebuilt.
+    pub fn request_resync(&mut self) {
+        self.resync = true;
+    }
+
+    /// The keys held down on any of the devices. Devices which fail are left out, as they're
+    /// noticed failing when next read.
+    fn held_keys(&self) -> KeySet {
+        let mut held = KeySet::default();
+        for device in self.slots.iter().filter_map(|slot| slot.device.as_ref()) {
+            for key in device.held_keys().unwrap_or_default().iter() {
+                held.insert(key);
+            }
+        }
+        held
+    }
+
+    pub fn ungrab(&mut self) {
+        if self.grabbed_at.is_none() {
+            return;
+        }
+        for device in self
+            .slots
+            .iter_mut()
+            .filter_map(|slot| slot.device.as_mut())
+        {
+            // The device may well be gone, in which case there's nothing left to ungrab.
+            let _ = device.0.ungrab();
+        }
+        self.grabbed_at = None;
+    }
+
+    /// Start reading `device` in slot `index`, grabbing it if the others are.
+    fn open(&mut self, index: usize, mut device: Device) -> Result<(), DeviceError> {
+        use_monotonic_timestamps(&device)?;
+        if self.grabbed_at.is_some() {
+            grab_device(&mut device)?;
+        }
+        self.epoll
+            .add(device.0.as_raw_fd(), EpollFlags::EPOLLIN, index as u64)?;
+        self.slots[index].device = Some(device);
+        Ok(())
+    }
+
+    /// Report the device in slot `index` failing with `err`, and close it until it's retried.
+    fn fail(&mut self, index: usize, err: DeviceError) {
+        let now = self.clock.now();
+        let slot = &mut self.slots[index];
+        // Closing the device also takes it out of epoll. Any keys held down on it can't be
+        // released any more.
+        let was_open = slot.device.take().is_some();
+        slot.dropping = false;
+        slot.failures += 1;
+        let delay = retry_delay(slot.failures);
+        slot.retry_at = now + delay;
+        log::error!(
+            "Device {} failed: {}. Carrying on without it, and retrying in {:?}",
+            slot.label(),
+            describe_failure(&err),
+            delay
+        );
+        self.resync |= was_open;
+    }
+
+    /// Try reopening the devices which failed and are due a retry at `now`.
+    fn retry_failed(&mut self, now: Duration) {
+        for index in 0..self.slots.len() {
+            let slot = &self.slots[index];
+            if slot.device.is_some() || slot.retry_at > now {
+                continue;
+            }
+            let open: Vec<&Path> = self
+                .slots
+                .iter()
+                .filter_map(|slot| slot.device.as_ref()?.path())
+                .collect();
+            let (name, phys) = (slot.name.as_deref(), slot.phys.as_deref());
+            let found = match &self.opener {
+                Some(opener) => opener.find_device(name, phys, &open),
+                None => Ok(find_device(name, phys, &open)),
+            };
+            let result = match found {
+                Ok(None) => Err(DeviceError::DeviceNotFound(slot.label())),
+                Ok(Some(device)) => self.open(index, device),
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) => {
+                    let slot = &mut self.slots[index];
+                    println!("Reopened device {}", slot.label());
+                    slot.failures = 0;
+                    self.resync = true;
+                }
+                Err(err) => self.fail(index, err),
+            }
+        }
+    }
+
+    /// When the next failed device is due a retry.
+    fn next_retry(&self) -> Option<Duration> {
+        self.slots
+            .iter()
+            .filter(|slot| slot.device.is_none())
+            .map(|slot| slot.retry_at)
+            .min()
+    }
+
+    /// Report `Poll::ConfigChanged` whenever the file at `path` is written or replaced.
+    pub fn watch_config(&mut self, path: &Path) -> Result<(), DeviceError> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| DeviceError::Message(format!("Config path {:?} isn't a file", path)))?;
+        let directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        };
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .map_err(std::io::Error::from)?;
+        let watcher = ConfigWatcher {
+            inotify,
+            file_name: file_name.to_owned(),
+        };
+        inotify
+            .add_watch(
+                directory,
+                AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+            )
+            .map_err(std::io::Error::from)?;
+        self.epoll
+            .add(inotify.as_raw_fd(), EpollFlags::EPOLLIN, CONFIG)?;
+        self.config = Some(watcher);
+        Ok(())
+    }
+
+    /// Read the events waiting on device `index`. The device is read directly rather than through
+    /// evdev, which only makes up for dropped events once more events arrive, so that the keys
+    /// held can be resynced as soon as the kernel reports dropping events.
+    fn read_events(&mut self, index: usize) -> Result<(), DeviceError> {
+        let mut buffer: [nix::libc::input_event; READ_BATCH] = unsafe { std::mem::zeroed() };
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(
+                buffer.as_mut_ptr() as *mut u8,
+                std::mem::size_of_val(&buffer),
+            )
+        };
+        let fd = match &self.slots[index].device {
+            Some(device) => device.0.as_raw_fd(),
+            None => return Ok(()),
+        };
+        let read = nix::unistd::read(fd, bytes).map_err(std::io::Error::from)?;
+        let count = read / std::mem::size_of::<nix::libc::input_event>();
+
+        for event in buffer[..count].iter().map(|raw| InputEvent::from(*raw)) {
+            match event.destructure() {
+                EventSummary::Synchronization(_, SynchronizationCode::SYN_DROPPED, _) => {
+                    log::warn!("The kernel dropped events, resyncing the keys held");
+                    self.slots[index].dropping = true;
+                }
+                EventSummary::Synchronization(_, SynchronizationCode::SYN_REPORT, _)
+                    if self.slots[index].dropping =>
+                {
+                    self.slots[index].dropping = false;
+                    self.resync = true;
+                }
+                _ if self.slots[index].dropping => {}
+                EventSummary::Key(_, key, value) => {
+                    let (state, time) = match (
+                        KeyState::from_value(value),
+                        event.timestamp().duration_since(SystemTime::UNIX_EPOCH),
+                    ) {
+                        (Some(state), Ok(time)) => (state, time),
+                        _ => continue,
+                    };
+                    if self.grabbed_at.is_some_and(|grabbed_at| time < grabbed_at) {
+                        continue;
+                    }
+                    self.pending.push_back(KeyEvent::new(key, state, time));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn set_timer(&self, deadline: Option<Duration>) -> Result<(), DeviceError> {
+        match deadline {
+            None => self.timer.unset(),
+            Some(deadline) => self.timer.set(
+                Expiration::OneShot(TimeSpec::from(deadline)),
+                TimerSetTimeFlags::TFD_TIMER_ABSTIME,
+            ),
+        }
+        .map_err(std::io::Error::from)?;
+        Ok(())
+    }
+
+    /// Whether any of the pending inotify events are for the config file.
+    fn config_changed(&mut self) -> Result<bool, DeviceError> {
+        let watcher = match &self.config {
+            Some(watcher) => watcher,
+            None => return Ok(false),
+        };
+        let events = watcher
+            .inotify
+            .read_events()
+            .map_err(std::io::Error::from)?;
+        Ok(events
+            .iter()
+            .any(|event| event.name.as_ref() == Some(&watcher.file_name)))
+    }
+
+    /// Read every pending signal, returning the last one.
+    fn read_signals(&mut self) -> Result<Option<Signal>, DeviceError> {
+        let mut last = None;
+        while let Some(info) = self.signals.read_signal().map_err(std::io::Error::from)? {
+            last = Signal::try_from(info.ssi_signo as c_int).ok().or(last);
+        }
+        Ok(last)
+    }
+}
+
+impl Drop for EvdevSource {
+    fn drop(&mut self) {
+        self.ungrab();
+    }
+}
+
+impl EventSource for EvdevSource {
+    fn poll(&mut self, deadline: Option<Duration>) -> Result<Poll, DeviceError> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Poll::Event(event));
+            }
+
+            let now = self.clock.now();
+            self.retry_failed(now);
+            // Only once the events read before are returned, as the state includes them.
+            if self.resync {
+                self.resync = false;
+                return Ok(Poll::Resync {
+                    held: self.held_keys(),
+                    time: now,
+                });
+            }
+            if deadline.is_some_and(|deadline| deadline <= now) {
+                return Ok(Poll::Timeout(now));
+            }
+            self.set_timer([deadline, self.next_retry()].into_iter().flatten().min())?;
+
+            let mut ready = [EpollEvent::empty(); 16];
+            let count = match epoll_wait(self.epoll.0, &mut ready, -1) {
+                Err(nix::errno::Errno::EINTR) => continue,
+                result => result.map_err(std::io::Error::from)?,
+            };
+
+            let mut config_changed = false;
+            for event in &ready[..count] {
+                match event.data() {
+                    // Only clears the timer, deadlines are checked against the clock.
+                    TIMER => {
+                        let _ = self.timer.wait();
+                    }
+                    SIGNALS => match self.read_signals()? {
+                        Some(Signal::SIGHUP) => config_changed = true,
+                        Some(_) => return Ok(Poll::Closed),
+                        None => {}
+                    },
+                    CONFIG => config_changed |= self.config_changed()?,
+                    index => {
+                        if let Err(err) = self.read_events(index as usize) {
+                            self.fail(index as usize, err);
+                        }
+                    }
+                }
+            }
+            // Devices are read in whatever order epoll reports them, so events from different
+            // devices are put back in the order they happened.
+            self.pending
+                .make_contiguous()
+                .sort_by_key(|event| event.time);
+
+            if config_changed {
+                return Ok(Poll::ConfigChanged);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_evdev_source {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::Key;
+    use std::fs;
+
+    #[test]
+    fn waits_until_keys_are_released() {
+        let clock = VirtualClock::default();
+        let held = wait_for_release(
+            &clock,
+            Duration::from_secs(2),
+            || match clock.now() < Duration::from_millis(50) {
+                true => Ok(KeySet::from_iter([Key::KEY_ENTER])),
+                false => Ok(KeySet::default()),
+            },
+            |interval| clock.advance_to(clock.now() + interval),
+        )
+        .unwrap();
+        assert!(held.is_empty());
+        assert_eq!(clock.now(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn gives_up_waiting_after_timeout() {
+        let clock = VirtualClock::default();
+        let held = wait_for_release(
+            &clock,
+            Duration::from_secs(2),
+            || Ok(KeySet::from_iter([Key::KEY_ENTER])),
+            |interval| clock.advance_to(clock.now() + interval),
+        )
+        .unwrap();
+        assert_eq!(held, KeySet::from_iter([Key::KEY_ENTER]));
+        assert_eq!(clock.now(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn retries_back_off_up_to_a_limit() {
+        assert_eq!(retry_delay(1), Duration::from_millis(500));
+        assert_eq!(retry_delay(2), Duration::from_secs(1));
+        assert_eq!(retry_delay(4), Duration::from_secs(4));
+        assert_eq!(retry_delay(100), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn common_failures_are_explained() {
+        let failure = |errno| DeviceError::IO(std::io::Error::from_raw_os_error(errno));
+        assert!(describe_failure(&failure(nix::libc::EACCES)).starts_with("permission denied"));
+        assert!(describe_failure(&failure(nix::libc::EBUSY)).starts_with("busy"));
+        assert!(describe_failure(&failure(nix::libc::ENODEV)).starts_with("disconnected"));
+    }
+
+    #[test]
+    fn timer_expires_at_deadline() {
+        let mut source = EvdevSource::new(Vec::new()).unwrap();
+        let deadline = MonotonicClock.now() + Duration::from_millis(20);
+        match source.poll(Some(deadline)).unwrap() {
+            Poll::Timeout(now) => assert!(now >= deadline),
+            poll => panic!("Expected a timeout, got {:?}", poll),
+        }
+    }
+
+    #[test]
+    fn writing_config_file_is_reported() {
+        let directory = std::env::temp_dir().join(format!("remapper-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("config.toml");
+        fs::write(&path, "").unwrap();
+
+        let mut source = EvdevSource::new(Vec::new()).unwrap();
+        source.watch_config(&path).unwrap();
+        fs::write(directory.join("other.toml"), "").unwrap();
+        fs::write(&path, "[devices]").unwrap();
+        let deadline = MonotonicClock.now() + Duration::from_secs(5);
+        assert_eq!(source.poll(Some(deadline)).unwrap(), Poll::ConfigChanged);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
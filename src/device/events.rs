@@ -0,0 +1,291 @@
+// Where the key events to remap come from and where the remapped events go. The evdev
+// implementations read from grabbed devices (in `event_loop`) and write to a virtual device,
+// while the in-memory ones play back scripted events in virtual time and record the output, so
+// that the whole remapping loop can be tested without any devices.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use evdev::{EventType, InputEvent};
+
+use super::device::VirtualDevice;
+use crate::clock::VirtualClock;
+use crate::errors::DeviceError;
+use crate::key::{KeyEvent, KeySet, KeyState};
+
+/// The result of waiting on an `EventSource`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Poll {
+    Event(KeyEvent),
+    /// The deadline passed without any event, at the given time.
+    Timeout(Duration),
+    /// The keys held down on the devices at the given time, which events may not have told of,
+    /// e.g. when the devices were grabbed or the kernel dropped events.
+    Resync {
+        held: KeySet,
+        time: Duration,
+    },
+    /// The config file changed, or reloading it was asked for.
+    ConfigChanged,
+    /// No more events will come, e.g. at the end of a script or when asked to stop.
+    Closed,
+}
+
+pub trait EventSource {
+    /// Wait for the next key event, but no later than `deadline`. Times are measured from the
+    /// same origin as the times of the events.
+    fn poll(&mut self, deadline: Option<Duration>) -> Result<Poll, DeviceError>;
+}
+
+pub trait EventSink {
+    /// Write out `events` together, as if they happened at once.
+    fn emit(&mut self, events: &[KeyEvent]) -> Result<(), DeviceError>;
+}
+
+impl<S: EventSink + ?Sized> EventSink for &mut S {
+    fn emit(&mut self, events: &[KeyEvent]) -> Result<(), DeviceError> {
+        (**self).emit(events)
+    }
+}
+
+/// Keeps track of the keys held down through a sink, so that they can all be released when
+/// remapping stops for any reason, as otherwise other programs would see them stuck down. Keys
+/// are also released when it's dropped, so that returning with an error or panicking can't
+/// leave keys stuck either.
+pub struct ReleasingSink<S: EventSink> {
+    sink: S,
+    held: KeySet,
+    /// Time of the latest event, for the releases.
+    time: Duration,
+}
+
+impl<S: EventSink> ReleasingSink<S> {
+    pub fn new(sink: S) -> ReleasingSink<S> {
+        ReleasingSink {
+            sink,
+            held: KeySet::default(),
+            time: Duration::ZERO,
+        }
+    }
+
+    /// Release every key held down.
+    pub fn release_all(&mut self) -> Result<(), DeviceError> {
+        let releases: Vec<KeyEvent> = self
+            .held
+            .iter()
+            .map(|key| KeyEvent::new(key, KeyState::Released, self.time))
+            .collect();
+        if releases.is_empty() {
+            return Ok(());
+        }
+        self.held = KeySet::default();
+        self.sink.emit(&releases)
+    }
+}
+
+impl<S: EventSink> EventSink for ReleasingSink<S> {
+    fn emit(&mut self, events: &[KeyEvent]) -> Result<(), DeviceError> {
+        for event in events {
+            self.time = self.time.max(event.time);
+            match event.state {
+                KeyState::Pressed => self.held.insert(event.key),
+                KeyState::Released => self.held.remove(event.key),
+                KeyState::Repeated => false,
+            };
+        }
+        self.sink.emit(events)
+    }
+}
+
+impl<S: EventSink> Drop for ReleasingSink<S> {
+    fn drop(&mut self) {
+        if let Err(err) = self.release_all() {
+            log::error!("Failed to release keys held down: {}", err);
+        }
+    }
+}
+
+/// Writes key events to a virtual device.
+pub struct EvdevSink(pub VirtualDevice);
+
+impl EventSink for EvdevSink {
+    fn emit(&mut self, events: &[KeyEvent]) -> Result<(), DeviceError> {
+        let events: Vec<InputEvent> = events
+            .iter()
+            .map(|event| InputEvent::new(EventType::KEY.0, event.key.code(), event.state.value()))
+            .collect();
+        Ok(self.0 .0.emit(&events)?)
+    }
+}
+
+/// Plays back a script of key events in virtual time, which only passes as far as the events and
+/// deadlines it's polled with.
+#[derive(Debug, Default)]
+pub struct ScriptedSource {
+    events: VecDeque<KeyEvent>,
+    clock: VirtualClock,
+}
+
+impl ScriptedSource {
+    pub fn new(events: impl IntoIterator<Item = KeyEvent>) -> ScriptedSource {
+        ScriptedSource {
+            events: events.into_iter().collect(),
+            clock: VirtualClock::default(),
+        }
+    }
+
+    /// The clock of the virtual time, for the engine to read its timers by.
+    #[cfg(test)]
+    pub fn clock(&self) -> VirtualClock {
+        self.clock.clone()
+    }
+}
+
+impl EventSource for ScriptedSource {
+    fn poll(&mut self, deadline: Option<Duration>) -> Result<Poll, DeviceError> {
+        let next_time = self.events.front().map(|event| event.time);
+        let poll = match (next_time, deadline) {
+            (Some(time), Some(deadline)) if deadline < time => Poll::Timeout(deadline),
+            (Some(_), _) => Poll::Event(self.events.pop_front().unwrap()),
+            (None, Some(deadline)) => Poll::Timeout(deadline),
+            (None, None) => Poll::Closed,
+        };
+        match poll {
+            Poll::Event(event) => self.clock.advance_to(event.time),
+            Poll::Timeout(time) => self.clock.advance_to(time),
+            Poll::Resync { .. } | Poll::ConfigChanged | Poll::Closed => {}
+        }
+        Ok(poll)
+    }
+}
+
+/// Keeps every event written to it.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    pub events: Vec<KeyEvent>,
+}
+
+impl EventSink for MemorySink {
+    fn emit(&mut self, events: &[KeyEvent]) -> Result<(), DeviceError> {
+        self.events.extend_from_slice(events);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_scripted_source {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::key::KeyState;
+    use crate::Key;
+
+    fn press(milliseconds: u64) -> KeyEvent {
+        KeyEvent::new(
+            Key::KEY_A,
+            KeyState::Pressed,
+            Duration::from_millis(milliseconds),
+        )
+    }
+
+    #[test]
+    fn events_are_returned_in_order_then_closed() {
+        let mut source = ScriptedSource::new([press(0), press(10)]);
+        assert_eq!(source.poll(None).unwrap(), Poll::Event(press(0)));
+        assert_eq!(source.poll(None).unwrap(), Poll::Event(press(10)));
+        assert_eq!(source.poll(None).unwrap(), Poll::Closed);
+    }
+
+    #[test]
+    fn deadline_before_next_event_times_out() {
+        let mut source = ScriptedSource::new([press(100)]);
+        let deadline = Duration::from_millis(50);
+        assert_eq!(
+            source.poll(Some(deadline)).unwrap(),
+            Poll::Timeout(deadline)
+        );
+        assert_eq!(
+            source.poll(Some(deadline * 2)).unwrap(),
+            Poll::Event(press(100))
+        );
+    }
+
+    #[test]
+    fn clock_follows_the_events_and_deadlines() {
+        let mut source = ScriptedSource::new([press(100)]);
+        let clock = source.clock();
+        source.poll(Some(Duration::from_millis(50))).unwrap();
+        assert_eq!(clock.now(), Duration::from_millis(50));
+        source.poll(None).unwrap();
+        assert_eq!(clock.now(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn deadline_after_last_event_times_out_before_closing() {
+        let mut source = ScriptedSource::new([]);
+        let deadline = Duration::from_millis(50);
+        assert_eq!(
+            source.poll(Some(deadline)).unwrap(),
+            Poll::Timeout(deadline)
+        );
+        assert_eq!(source.poll(None).unwrap(), Poll::Closed);
+    }
+}
+
+#[cfg(test)]
+mod test_releasing_sink {
+    use super::*;
+    use crate::Key;
+
+    fn event(key: Key, state: KeyState, milliseconds: u64) -> KeyEvent {
+        KeyEvent::new(key, state, Duration::from_millis(milliseconds))
+    }
+
+    #[test]
+    fn held_keys_are_released_when_dropped() {
+        let mut memory = MemorySink::default();
+        {
+            let mut sink = ReleasingSink::new(&mut memory);
+            sink.emit(&[
+                event(Key::KEY_LEFTSHIFT, KeyState::Pressed, 0),
+                event(Key::KEY_A, KeyState::Pressed, 10),
+                event(Key::KEY_A, KeyState::Released, 20),
+            ])
+            .unwrap();
+        }
+        assert_eq!(
+            memory.events[3..],
+            [event(Key::KEY_LEFTSHIFT, KeyState::Released, 20)]
+        );
+    }
+
+    #[test]
+    fn held_keys_are_released_on_panic() {
+        let mut memory = MemorySink::default();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut sink = ReleasingSink::new(&mut memory);
+            sink.emit(&[event(Key::KEY_A, KeyState::Pressed, 0)])
+                .unwrap();
+            panic!("Engine failed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(
+            memory.events,
+            [
+                event(Key::KEY_A, KeyState::Pressed, 0),
+                event(Key::KEY_A, KeyState::Released, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_are_only_released_once() {
+        let mut memory = MemorySink::default();
+        {
+            let mut sink = ReleasingSink::new(&mut memory);
+            sink.emit(&[event(Key::KEY_A, KeyState::Pressed, 0)])
+                .unwrap();
+            sink.release_all().unwrap();
+        }
+        assert_eq!(memory.events.len(), 2);
+    }
+}
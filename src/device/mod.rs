@@ -1,3 +1,9 @@
+pub mod class;
+#[allow(clippy::module_inception)]
 mod device;
+pub mod event_loop;
+pub mod events;
+pub mod opener;
 
-pub use device::{get_all_devices, Device, DeviceInfo, VirtualDevice};
+pub use class::DeviceClass;
+pub use device::{get_all_devices, DeviceInfo, VirtualDevice};
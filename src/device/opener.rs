@@ -0,0 +1,308 @@
+// Reopening devices once root is given up. A helper process forked before dropping privileges
+// keeps root, finds and opens devices when asked, and passes their file descriptors back over a
+// socket, so a device unplugged and plugged back in can be grabbed again without the event loop
+// needing permission to open it.
+
+use std::ffi::OsStr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::sys::socket::{
+    recvmsg, sendmsg, socketpair, AddressFamily, ControlMessage, ControlMessageOwned, MsgFlags,
+    SockFlag, SockType,
+};
+use nix::sys::uio::IoVec;
+use nix::unistd::{fork, ForkResult};
+
+use super::device::{find_device, Device, DeviceInfo};
+use crate::errors::DeviceError;
+
+/// Longer than any request or reply, which hold device names, phys and paths.
+const MAX_MESSAGE: usize = 4096;
+
+const FOUND: u8 = b'+';
+const NOT_FOUND: u8 = b'-';
+
+/// Asks the helper process to find and open devices.
+pub struct DeviceOpener {
+    /// The helper exits once this is closed.
+    socket: OwnedFd,
+}
+
+impl DeviceOpener {
+    /// Fork the helper, which keeps the privileges this process has now. Call it before opening
+    /// any device, so the helper doesn't hold them open.
+    pub fn spawn() -> Result<DeviceOpener, DeviceError> {
+        let (parent, child) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .map_err(std::io::Error::from)?;
+        let (parent, child) =
+            unsafe { (OwnedFd::from_raw_fd(parent), OwnedFd::from_raw_fd(child)) };
+        match unsafe { fork() }.map_err(std::io::Error::from)? {
+            ForkResult::Child => {
+                drop(parent);
+                // Stopping the remapper from the terminal closes the socket, which stops the
+                // helper, so it needn't stop on the same signals.
+                for terminal_signal in [Signal::SIGINT, Signal::SIGHUP] {
+                    let _ = unsafe { signal(terminal_signal, SigHandler::SigIgn) };
+                }
+                serve(child.as_raw_fd());
+                unsafe { nix::libc::_exit(0) }
+            }
+            ForkResult::Parent { child: pid } => {
+                log::info!("Started helper process {} to reopen devices", pid);
+                Ok(DeviceOpener { socket: parent })
+            }
+        }
+    }
+
+    /// Find the device called `name` and plugged in at `phys`, other than any at the paths
+    /// `skip`, as `find_device` would with the helper's privileges.
+    pub fn find_device(
+        &self,
+        name: Option<&str>,
+        phys: Option<&str>,
+        skip: &[&Path],
+    ) -> Result<Option<Device>, DeviceError> {
+        let socket = self.socket.as_raw_fd();
+        send_message(socket, &encode_request(name, phys, skip), None)?;
+        let mut buffer = [0; MAX_MESSAGE];
+        let (read, fd) = receive_message(socket, &mut buffer)?;
+        match (buffer[..read].split_first(), fd) {
+            (Some((&FOUND, path)), Some(fd)) => Ok(Some(Device::new(
+                evdev::Device::from_fd(fd)?,
+                PathBuf::from(OsStr::from_bytes(path)),
+            ))),
+            (Some((&NOT_FOUND, _)), None) => Ok(None),
+            _ => Err(DeviceError::Message(
+                "The helper reopening devices stopped or replied wrongly".to_owned(),
+            )),
+        }
+    }
+}
+
+/// Answer requests on `socket` until it's closed.
+fn serve(socket: RawFd) {
+    let mut buffer = [0; MAX_MESSAGE];
+    loop {
+        let read = match receive_message(socket, &mut buffer) {
+            Ok((0, _)) | Err(_) => return,
+            Ok((read, _)) => read,
+        };
+        let device = decode_request(&buffer[..read])
+            .and_then(|(name, phys, skip)| {
+                let skip: Vec<&Path> = skip.iter().map(PathBuf::as_path).collect();
+                find_device(name.as_deref(), phys.as_deref(), &skip)
+            })
+            .and_then(|device| Some((device.path()?.to_owned(), device)));
+        let sent = match &device {
+            Some((path, device)) => {
+                let mut reply = vec![FOUND];
+                reply.extend_from_slice(path.as_os_str().as_bytes());
+                send_message(socket, &reply, Some(device.0.as_raw_fd()))
+            }
+            None => send_message(socket, &[NOT_FOUND], None),
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+}
+
+/// Send `data` on `socket`, with `fd` duplicated into the receiving process.
+fn send_message(socket: RawFd, data: &[u8], fd: Option<RawFd>) -> Result<(), DeviceError> {
+    let fds: Vec<RawFd> = fd.into_iter().collect();
+    let control: Vec<ControlMessage> = match fds.is_empty() {
+        true => vec![],
+        false => vec![ControlMessage::ScmRights(&fds)],
+    };
+    // Rust ignores SIGPIPE, so a helper which has stopped is an error rather than killing the
+    // remapper.
+    sendmsg(
+        socket,
+        &[IoVec::from_slice(data)],
+        &control,
+        MsgFlags::empty(),
+        None,
+    )
+    .map_err(std::io::Error::from)?;
+    Ok(())
+}
+
+/// Receive a message on `socket` into `buffer`, returning its length and the file descriptor
+/// passed with it, if any. A length of 0 means the other end has closed the socket.
+fn receive_message(
+    socket: RawFd,
+    buffer: &mut [u8],
+) -> Result<(usize, Option<OwnedFd>), DeviceError> {
+    let mut control = nix::cmsg_space!([RawFd; 1]);
+    let message = recvmsg(
+        socket,
+        &[IoVec::from_mut_slice(buffer)],
+        Some(&mut control),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )
+    .map_err(std::io::Error::from)?;
+    let mut fds = message.cmsgs().flat_map(|control| match control {
+        ControlMessageOwned::ScmRights(fds) => fds,
+        _ => vec![],
+    });
+    let fd = fds.next().map(|fd| unsafe { OwnedFd::from_raw_fd(fd) });
+    // Only one is ever sent, but any more mustn't leak.
+    fds.for_each(|fd| drop(unsafe { OwnedFd::from_raw_fd(fd) }));
+    Ok((message.bytes, fd))
+}
+
+/// The fields of a request separated by NUL, which names, phys and paths can't contain, each
+/// optional field marked as present or not by its first byte.
+fn encode_request(name: Option<&str>, phys: Option<&str>, skip: &[&Path]) -> Vec<u8> {
+    let optional = |field: Option<&str>| match field {
+        Some(field) => [&[FOUND], field.as_bytes()].concat(),
+        None => vec![NOT_FOUND],
+    };
+    let mut fields = vec![optional(name), optional(phys)];
+    fields.extend(skip.iter().map(|path| path.as_os_str().as_bytes().to_vec()));
+    fields.join(&0)
+}
+
+type Request = (Option<String>, Option<String>, Vec<PathBuf>);
+
+fn decode_request(request: &[u8]) -> Option<Request> {
+    let optional = |field: &[u8]| match field.split_first() {
+        Some((&FOUND, field)) => Some(Some(String::from_utf8(field.to_vec()).ok()?)),
+        Some((&NOT_FOUND, [])) => Some(None),
+        _ => None,
+    };
+    let mut fields = request.split(|byte| *byte == 0);
+    let name = optional(fields.next()?)?;
+    let phys = optional(fields.next()?)?;
+    let skip = fields
+        .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+        .collect();
+    Some((name, phys, skip))
+}
+
+#[cfg(test)]
+mod test_device_opener {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::process::Command;
+
+    /// Set for the test process which spawns the helper, see `in_own_process`.
+    const OWN_PROCESS_VAR: &str = "CHORDED_KEY_REMAPPER_TEST_OWN_PROCESS";
+
+    fn socket_pair() -> (OwnedFd, OwnedFd) {
+        let (a, b) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .unwrap();
+        unsafe { (OwnedFd::from_raw_fd(a), OwnedFd::from_raw_fd(b)) }
+    }
+
+    #[test]
+    fn requests_are_decoded_as_encoded() {
+        let skip = [
+            Path::new("/dev/input/event3"),
+            Path::new("/dev/input/event10"),
+        ];
+        let request = encode_request(Some("My Keyboard"), None, &skip);
+        assert_eq!(
+            decode_request(&request),
+            Some((
+                Some("My Keyboard".to_owned()),
+                None,
+                skip.iter().map(|path| path.to_path_buf()).collect()
+            ))
+        );
+        assert_eq!(
+            decode_request(&encode_request(None, Some(""), &[])),
+            Some((None, Some("".to_owned()), vec![]))
+        );
+        assert_eq!(decode_request(b"nonsense"), None);
+    }
+
+    #[test]
+    fn fds_are_passed_to_the_other_end() {
+        let (sender, receiver) = socket_pair();
+        let (pipe_out, pipe_in) = nix::unistd::pipe().unwrap();
+        let (pipe_out, pipe_in) = unsafe {
+            (
+                OwnedFd::from_raw_fd(pipe_out),
+                OwnedFd::from_raw_fd(pipe_in),
+            )
+        };
+
+        send_message(sender.as_raw_fd(), b"+/dev/null", Some(pipe_in.as_raw_fd())).unwrap();
+        drop(pipe_in);
+        let mut buffer = [0; MAX_MESSAGE];
+        let (read, fd) = receive_message(receiver.as_raw_fd(), &mut buffer).unwrap();
+        assert_eq!(&buffer[..read], b"+/dev/null");
+
+        // The passed fd is another reference to the same pipe.
+        std::fs::File::from(fd.unwrap()).write_all(b"key").unwrap();
+        let mut written = String::new();
+        std::fs::File::from(pipe_out)
+            .read_to_string(&mut written)
+            .unwrap();
+        assert_eq!(written, "key");
+    }
+
+    #[test]
+    fn messages_can_be_sent_without_an_fd() {
+        let (sender, receiver) = socket_pair();
+        send_message(sender.as_raw_fd(), &[NOT_FOUND], None).unwrap();
+        let mut buffer = [0; MAX_MESSAGE];
+        let (read, fd) = receive_message(receiver.as_raw_fd(), &mut buffer).unwrap();
+        assert_eq!(&buffer[..read], &[NOT_FOUND]);
+        assert!(fd.is_none());
+
+        drop(sender);
+        let (read, _) = receive_message(receiver.as_raw_fd(), &mut buffer).unwrap();
+        assert_eq!(read, 0);
+    }
+
+    /// Whether the test `name` is running in a process of its own. If not, it's run again in a new
+    /// process of the test binary, without other tests' threads, and must then return. Forking
+    /// with other threads running could leave the child waiting on a lock they held.
+    fn in_own_process(name: &str) -> bool {
+        if std::env::var_os(OWN_PROCESS_VAR).is_some() {
+            return true;
+        }
+        let (_, module) = module_path!().split_once("::").unwrap();
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", &format!("{}::{}", module, name)])
+            .arg("--test-threads=1")
+            .env(OWN_PROCESS_VAR, "1")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", stdout);
+        assert!(stdout.contains("1 passed"), "{}", stdout);
+        false
+    }
+
+    #[test]
+    fn the_helper_replies_when_no_device_is_found() {
+        if !in_own_process("the_helper_replies_when_no_device_is_found") {
+            return;
+        }
+        let opener = DeviceOpener::spawn().unwrap();
+        let found = opener
+            .find_device(
+                Some("No such device"),
+                None,
+                &[Path::new("/dev/input/event0")],
+            )
+            .unwrap();
+        assert!(found.is_none());
+    }
+}
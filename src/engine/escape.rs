@@ -0,0 +1,76 @@
+// The escape chord, which stops the remapper whatever the maps do. It watches the keys as they
+// are pressed on the devices, before any stage sees them, so no config can swallow it.
+
+use crate::key::{KeyEvent, KeySet, KeyState};
+use crate::Key;
+
+pub struct EscapeChord {
+    keys: KeySet,
+    /// The keys held down on the devices.
+    held: KeySet,
+}
+
+impl EscapeChord {
+    /// An escape chord of `keys`, or none at all if there are no keys.
+    pub fn new(keys: &[Key]) -> EscapeChord {
+        EscapeChord {
+            keys: keys.iter().copied().collect(),
+            held: KeySet::default(),
+        }
+    }
+
+    /// Take the keys held down on the devices to be `held`, after events were missed.
+    pub fn resync(&mut self, held: &KeySet) {
+        self.held = *held;
+    }
+
+    /// Track `event`, returning whether it completes the chord.
+    pub fn process(&mut self, event: &KeyEvent) -> bool {
+        match event.state {
+            KeyState::Pressed => {
+                self.held.insert(event.key);
+                !self.keys.is_empty()
+                    && self.keys.contains(event.key)
+                    && self.keys.iter().all(|key| self.held.contains(key))
+            }
+            KeyState::Released => {
+                self.held.remove(event.key);
+                false
+            }
+            KeyState::Repeated => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_escape_chord {
+    use super::*;
+    use crate::engine::test_utils::{press, release};
+
+    fn chord() -> EscapeChord {
+        EscapeChord::new(&[Key::KEY_ESC, Key::KEY_BACKSPACE, Key::KEY_ENTER])
+    }
+
+    #[test]
+    fn completed_by_the_last_key_pressed_in_any_order() {
+        let mut chord = chord();
+        assert!(!chord.process(&press(Key::KEY_ENTER, 0)));
+        assert!(!chord.process(&press(Key::KEY_ESC, 10)));
+        assert!(chord.process(&press(Key::KEY_BACKSPACE, 500)));
+    }
+
+    #[test]
+    fn not_completed_if_a_key_is_released() {
+        let mut chord = chord();
+        chord.process(&press(Key::KEY_ESC, 0));
+        chord.process(&press(Key::KEY_BACKSPACE, 10));
+        chord.process(&release(Key::KEY_ESC, 20));
+        assert!(!chord.process(&press(Key::KEY_ENTER, 30)));
+    }
+
+    #[test]
+    fn empty_chord_is_never_completed() {
+        let mut chord = EscapeChord::new(&[]);
+        assert!(!chord.process(&press(Key::KEY_ESC, 0)));
+    }
+}
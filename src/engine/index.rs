@@ -0,0 +1,301 @@
+// Indexes the inputs of maps, so that finding which maps the pending events match doesn't take
+// longer the more maps there are. Chords are looked up by the set of keys pressed, and sequences
+// by walking a trie of their keys. Whether the keys pressed are part of a longer chord is found by
+// walking a trie of the chords' key codes in ascending order.
+
+#[cfg(test)]
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::key::{KeyEvent, KeySet, KeyState};
+use crate::mapping::{InputKind, Map};
+use crate::Key;
+
+/// The maps matched by some events.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Lookup {
+    /// The first map, in config order, whose input is exactly the events.
+    pub exact: Option<usize>,
+    /// Whether more presses could complete a chord.
+    pub chord_prefix: bool,
+    /// Whether more presses could complete a sequence.
+    pub sequence_prefix: bool,
+}
+
+/// A node of the trie of chords, reached by the key codes of a chord in ascending order.
+#[derive(Default)]
+struct ChordNode {
+    next: BTreeMap<u16, usize>,
+}
+
+#[derive(Default)]
+struct SequenceNode {
+    next: HashMap<Key, usize>,
+    map: Option<usize>,
+}
+
+pub struct MapIndex {
+    /// Each chord's keys, with the first map having them as input.
+    chords: HashMap<KeySet, usize>,
+    /// A trie of the key codes of chords, starting at the root node. It takes a node per key of
+    /// each chord at most, rather than a set per subset of its keys, which for chords of
+    /// `MAX_CHORD_KEYS` keys would be a thousand sets of a hundred bytes each.
+    chord_trie: Vec<ChordNode>,
+    /// A trie of the keys of sequences, starting at the root node.
+    sequences: Vec<SequenceNode>,
+    /// How many times the maps have been looked up, to check how many lookups an event takes.
+    #[cfg(test)]
+    pub lookups: Cell<usize>,
+}
+
+impl MapIndex {
+    /// Index `maps`, referring to them by their index in it.
+    pub fn new(maps: &[Map]) -> MapIndex {
+        let mut index = MapIndex {
+            chords: HashMap::new(),
+            chord_trie: vec![ChordNode::default()],
+            sequences: vec![SequenceNode::default()],
+            #[cfg(test)]
+            lookups: Cell::new(0),
+        };
+        for (map_index, map) in maps.iter().enumerate() {
+            // The config rejects maps which can't be played, but they're skipped all the same.
+            if map.check().is_err() {
+                continue;
+            }
+            match map.kind {
+                InputKind::Chord => index.add_chord(&map.input, map_index),
+                InputKind::Sequence => index.add_sequence(&map.input, map_index),
+            }
+        }
+        index
+    }
+
+    fn add_chord(&mut self, keys: &[Key], map_index: usize) {
+        if keys.is_empty() {
+            return;
+        }
+        self.chords
+            .entry(KeySet::from_iter(keys.iter().copied()))
+            .or_insert(map_index);
+        let mut node = 0;
+        for code in sorted_codes(keys.iter().copied()) {
+            node = match self.chord_trie[node].next.get(&code) {
+                Some(next) => *next,
+                None => {
+                    self.chord_trie.push(ChordNode::default());
+                    let next = self.chord_trie.len() - 1;
+                    self.chord_trie[node].next.insert(code, next);
+                    next
+                }
+            };
+        }
+    }
+
+    fn add_sequence(&mut self, keys: &[Key], map_index: usize) {
+        let mut node = 0;
+        for key in keys {
+            node = match self.sequences[node].next.get(key) {
+                Some(next) => *next,
+                None => {
+                    self.sequences.push(SequenceNode::default());
+                    let next = self.sequences.len() - 1;
+                    self.sequences[node].next.insert(*key, next);
+                    next
+                }
+            };
+        }
+        self.sequences[node].map.get_or_insert(map_index);
+    }
+
+    /// Which maps `events` match, regardless of their timing.
+    pub fn lookup(&self, events: &[KeyEvent]) -> Lookup {
+        #[cfg(test)]
+        self.lookups.set(self.lookups.get() + 1);
+        if events.is_empty() {
+            return Lookup::default();
+        }
+        let chord = self.chord_keys(events);
+        let sequence = self.sequence_node(events);
+
+        let chord_exact = chord.and_then(|keys| self.chords.get(&keys).copied());
+        let sequence_exact = sequence.and_then(|node| node.map);
+        Lookup {
+            exact: match (chord_exact, sequence_exact) {
+                (Some(chord), Some(sequence)) => Some(chord.min(sequence)),
+                (chord, sequence) => chord.or(sequence),
+            },
+            chord_prefix: chord.is_some()
+                && self.is_chord_prefix(
+                    &sorted_codes(events.iter().map(|event| event.key)),
+                    0,
+                    false,
+                ),
+            sequence_prefix: sequence.is_some_and(|node| !node.next.is_empty()),
+        }
+    }
+
+    /// The keys pressed by `events`, if they could be (part of) a chord, which is matched as soon
+    /// as all of its keys are down, so can't include releases.
+    fn chord_keys(&self, events: &[KeyEvent]) -> Option<KeySet> {
+        let mut keys = KeySet::default();
+        for event in events {
+            if event.state != KeyState::Pressed || !keys.insert(event.key) {
+                return None;
+            }
+        }
+        Some(keys)
+    }
+
+    /// Whether some chord has all the keys of `codes` and more, going down the chord trie from
+    /// `node`, having `skipped` some key of the chord which isn't in `codes`. Any branch for a
+    /// code below the next of `codes` may lead on to it, so the search can take a few branches
+    /// at each node, but never one for a code above it.
+    fn is_chord_prefix(&self, codes: &[u16], node: usize, skipped: bool) -> bool {
+        let node = &self.chord_trie[node];
+        match codes.split_first() {
+            None => skipped || !node.next.is_empty(),
+            Some((code, rest)) => {
+                node.next
+                    .get(code)
+                    .is_some_and(|next| self.is_chord_prefix(rest, *next, skipped))
+                    || node
+                        .next
+                        .range(..code)
+                        .any(|(_, next)| self.is_chord_prefix(codes, *next, true))
+            }
+        }
+    }
+
+    /// The node of the sequence trie reached by the keys pressed by `events`, if they could be
+    /// (part of) a sequence. Keys may be released before the next is pressed.
+    fn sequence_node(&self, events: &[KeyEvent]) -> Option<&SequenceNode> {
+        let mut node = &self.sequences[0];
+        let mut held = KeySet::default();
+        for event in events {
+            match event.state {
+                KeyState::Pressed if held.insert(event.key) => {
+                    node = &self.sequences[*node.next.get(&event.key)?];
+                }
+                KeyState::Released if held.contains(event.key) => {
+                    held.remove(event.key);
+                }
+                _ => return None,
+            }
+        }
+        Some(node)
+    }
+}
+
+/// The codes of `keys` in ascending order, without repeats.
+fn sorted_codes(keys: impl Iterator<Item = Key>) -> Vec<u16> {
+    let mut codes: Vec<u16> = keys.map(|key| key.code()).collect();
+    codes.sort_unstable();
+    codes.dedup();
+    codes
+}
+
+#[cfg(test)]
+mod test_map_index {
+    use super::*;
+    use crate::engine::test_utils::{press, release};
+
+    fn maps() -> Vec<Map> {
+        vec![
+            Map {
+                input: vec![Key::KEY_S, Key::KEY_D],
+                kind: InputKind::Chord,
+                output: vec![Key::KEY_UP],
+                fingers: vec![],
+            },
+            Map {
+                input: vec![Key::KEY_S, Key::KEY_D, Key::KEY_F],
+                kind: InputKind::Chord,
+                output: vec![Key::KEY_DOWN],
+                fingers: vec![],
+            },
+            Map {
+                input: vec![Key::KEY_J, Key::KEY_K],
+                kind: InputKind::Sequence,
+                output: vec![Key::KEY_ESC],
+                fingers: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn chord_keys_match_in_any_order() {
+        let index = MapIndex::new(&maps());
+        let lookup = index.lookup(&[press(Key::KEY_D, 0), press(Key::KEY_S, 10)]);
+        assert_eq!(lookup.exact, Some(0));
+        assert!(lookup.chord_prefix);
+        assert!(!lookup.sequence_prefix);
+    }
+
+    #[test]
+    fn part_of_a_chord_is_a_prefix() {
+        let index = MapIndex::new(&maps());
+        let lookup = index.lookup(&[press(Key::KEY_F, 0)]);
+        assert_eq!(lookup.exact, None);
+        assert!(lookup.chord_prefix);
+    }
+
+    #[test]
+    fn a_whole_chord_is_a_prefix_only_of_a_longer_chord() {
+        let index = MapIndex::new(&maps());
+        let lookup = index.lookup(&[press(Key::KEY_S, 0), press(Key::KEY_D, 10)]);
+        assert!(lookup.chord_prefix);
+        let lookup = index.lookup(&[
+            press(Key::KEY_F, 0),
+            press(Key::KEY_D, 10),
+            press(Key::KEY_S, 20),
+        ]);
+        assert_eq!(lookup.exact, Some(1));
+        assert!(!lookup.chord_prefix);
+    }
+
+    #[test]
+    fn keys_of_different_chords_are_not_a_prefix() {
+        let index = MapIndex::new(&[
+            maps()[0].clone(),
+            Map {
+                input: vec![Key::KEY_F, Key::KEY_G],
+                kind: InputKind::Chord,
+                output: vec![Key::KEY_DOWN],
+                fingers: vec![],
+            },
+        ]);
+        let lookup = index.lookup(&[press(Key::KEY_S, 0), press(Key::KEY_G, 10)]);
+        assert_eq!(lookup, Lookup::default());
+    }
+
+    #[test]
+    fn chords_cannot_include_releases() {
+        let index = MapIndex::new(&maps());
+        let lookup = index.lookup(&[
+            press(Key::KEY_S, 0),
+            release(Key::KEY_S, 10),
+            press(Key::KEY_D, 20),
+        ]);
+        assert_eq!(lookup, Lookup::default());
+    }
+
+    #[test]
+    fn sequences_can_include_releases() {
+        let index = MapIndex::new(&maps());
+        let lookup = index.lookup(&[
+            press(Key::KEY_J, 0),
+            release(Key::KEY_J, 10),
+            press(Key::KEY_K, 20),
+        ]);
+        assert_eq!(lookup.exact, Some(2));
+        assert!(!lookup.sequence_prefix);
+    }
+
+    #[test]
+    fn sequences_must_be_in_order() {
+        let index = MapIndex::new(&maps());
+        let lookup = index.lookup(&[press(Key::KEY_K, 0), press(Key::KEY_J, 10)]);
+        assert_eq!(lookup, Lookup::default());
+    }
+}
@@ -0,0 +1,415 @@
+// Remaps keys according to the maps of the config: single keys, chords of keys pressed
+// together, and sequences of keys pressed one after another.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use super::index::MapIndex;
+use super::{Event, Stage};
+use crate::config::schema::MappingsConfig;
+use crate::key::{KeyEvent, KeyState};
+use crate::mapping::{InputKind, Map};
+use crate::Key;
+
+/// A map which has been triggered, and whose input keys are still held down.
+struct ActiveMap {
+    /// Input keys still held, whose events now belong to the map.
+    held: Vec<Key>,
+    output: Vec<Key>,
+    /// The output is released as soon as any input key is.
+    released: bool,
+}
+
+pub struct MapStage {
+    maps: Vec<Map>,
+    index: MapIndex,
+    /// Keys which can start a map: any key of a chord, or the first key of a sequence. Other
+    /// keys are passed on without being held back whenever nothing is pending.
+    starting_keys: HashSet<Key>,
+    chord_timeout: Duration,
+    sequence_timeout: Duration,
+    /// Input events held back while they may be the start of a map.
+    pending: Vec<KeyEvent>,
+    active: Vec<ActiveMap>,
+    /// Time of the latest event or timeout.
+    now: Duration,
+}
+
+impl MapStage {
+    pub fn new(config: &MappingsConfig) -> MapStage {
+        let maps: Vec<Map> = config
+            .maps
+            .iter()
+            .flatten()
+            .filter(|map| !map.input.is_empty())
+            .cloned()
+            .collect();
+        let starting_keys = maps
+            .iter()
+            .flat_map(|map| match map.kind {
+                InputKind::Chord => &map.input[..],
+                InputKind::Sequence => &map.input[..1],
+            })
+            .copied()
+            .collect();
+        MapStage {
+            index: MapIndex::new(&maps),
+            maps,
+            starting_keys,
+            chord_timeout: Duration::from_millis(config.chord_timeout_ms),
+            sequence_timeout: Duration::from_millis(config.sequence_timeout_ms),
+            pending: Vec::new(),
+            active: Vec::new(),
+            now: Duration::ZERO,
+        }
+    }
+
+    /// When pending events stop being able to grow into a map of `kind`.
+    fn deadline(&self, kind: InputKind) -> Option<Duration> {
+        let mut presses = self.pending.iter().filter(|event| event.is_press());
+        match kind {
+            InputKind::Chord => Some(presses.next()?.time + self.chord_timeout),
+            InputKind::Sequence => Some(presses.next_back()?.time + self.sequence_timeout),
+        }
+    }
+
+    /// The kinds of map the pending events could still grow into, if more keys are pressed.
+    fn prefix_kinds(&self) -> Vec<InputKind> {
+        let lookup = self.index.lookup(&self.pending);
+        let mut kinds = Vec::new();
+        if lookup.chord_prefix {
+            kinds.push(InputKind::Chord);
+        }
+        if lookup.sequence_prefix {
+            kinds.push(InputKind::Sequence);
+        }
+        kinds
+    }
+
+    fn could_still_match(&self) -> bool {
+        self.prefix_kinds().into_iter().any(|kind| {
+            self.deadline(kind)
+                .is_some_and(|deadline| self.now < deadline)
+        })
+    }
+
+    /// Trigger maps or pass on events from the front of `pending`, until it's empty or the
+    /// remaining events could still become a map.
+    fn settle(&mut self, out: &mut Vec<Event>) {
+        while !self.pending.is_empty() && !self.could_still_match() {
+            // Trigger the longest map matched by the start of the pending events.
+            let longest_match = (1..=self.pending.len()).rev().find_map(|length| {
+                let map = self.index.lookup(&self.pending[..length]).exact?;
+                Some((length, self.maps[map].clone()))
+            });
+            match longest_match {
+                Some((length, map)) => {
+                    let events: Vec<KeyEvent> = self.pending.drain(..length).collect();
+                    self.trigger(map, &events, out);
+                }
+                None => {
+                    let event = self.pending.remove(0);
+                    self.pass(event, out);
+                }
+            }
+        }
+    }
+
+    fn trigger(&mut self, map: Map, events: &[KeyEvent], out: &mut Vec<Event>) {
+        let time = events.last().map_or(self.now, |event| event.time);
+        for key in &map.output {
+            out.push(Event::Output(KeyEvent::new(*key, KeyState::Pressed, time)));
+        }
+
+        let mut held: Vec<Key> = Vec::new();
+        for event in events {
+            match event.state {
+                KeyState::Pressed => held.push(event.key),
+                KeyState::Released => held.retain(|key| *key != event.key),
+                KeyState::Repeated => {}
+            }
+        }
+        let mut active = ActiveMap {
+            held,
+            output: map.output,
+            released: false,
+        };
+        if active.held.is_empty() {
+            release_output(&mut active, time, out);
+        } else {
+            self.active.push(active);
+        }
+    }
+
+    /// Pass on an event which isn't part of any new map.
+    fn pass(&mut self, event: KeyEvent, out: &mut Vec<Event>) {
+        let active = self
+            .active
+            .iter_mut()
+            .position(|active| active.held.contains(&event.key));
+        match (active, event.state) {
+            (None, _) => out.push(Event::Input(event)),
+            (Some(index), KeyState::Released) => {
+                let active = &mut self.active[index];
+                active.held.retain(|key| *key != event.key);
+                release_output(active, event.time, out);
+                if active.held.is_empty() {
+                    self.active.remove(index);
+                }
+            }
+            (Some(index), _) => {
+                // Repeat the output while the input is held, e.g. for an arrow key.
+                let active = &self.active[index];
+                if let (false, Some(key)) = (active.released, active.output.last()) {
+                    out.push(Event::Output(KeyEvent::new(
+                        *key,
+                        KeyState::Repeated,
+                        event.time,
+                    )));
+                }
+            }
+        }
+    }
+}
+
+fn release_output(active: &mut ActiveMap, time: Duration, out: &mut Vec<Event>) {
+    if active.released {
+        return;
+    }
+    active.released = true;
+    for key in active.output.iter().rev() {
+        out.push(Event::Output(KeyEvent::new(*key, KeyState::Released, time)));
+    }
+}
+
+impl Stage for MapStage {
+    fn process(&mut self, event: Event, out: &mut Vec<Event>) {
+        let event = match event {
+            Event::Output(_) => return out.push(event),
+            Event::Input(event) => event,
+        };
+        self.now = self.now.max(event.time);
+
+        let can_start_map = event.is_press() && self.starting_keys.contains(&event.key);
+        if self.pending.is_empty() && !can_start_map {
+            return self.pass(event, out);
+        }
+        if event.state == KeyState::Repeated {
+            if !self.pending.iter().any(|pending| pending.key == event.key) {
+                self.pass(event, out);
+            }
+            return;
+        }
+        self.pending.push(event);
+        self.settle(out);
+    }
+
+    fn timeout(&mut self, now: Duration, out: &mut Vec<Event>) {
+        self.now = self.now.max(now);
+        self.settle(out);
+    }
+
+    fn next_deadline(&self) -> Option<Duration> {
+        self.prefix_kinds()
+            .into_iter()
+            .filter_map(|kind| self.deadline(kind))
+            .filter(|deadline| self.now < *deadline)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod test_map_stage {
+    use super::super::test_utils::*;
+    use super::*;
+    use crate::Key;
+
+    const CONFIG: &str = r#"
+[mappings]
+chord_timeout_ms = 50
+sequence_timeout_ms = 300
+
+[mappings.maps]
+"s+d" = "up"
+"j k" = "esc"
+"capslock" = "C-a"
+"#;
+
+    #[test]
+    fn keys_in_no_map_pass_through_immediately() {
+        let mut engine = engine(CONFIG);
+        assert_eq!(
+            engine.process(press(Key::KEY_A, 0)),
+            vec![press(Key::KEY_A, 0)]
+        );
+        assert_eq!(engine.next_deadline(), None);
+    }
+
+    #[test]
+    fn key_only_later_in_a_sequence_passes_through_immediately() {
+        let mut engine = engine(CONFIG);
+        assert_eq!(
+            engine.process(press(Key::KEY_K, 0)),
+            vec![press(Key::KEY_K, 0)]
+        );
+        assert_eq!(engine.next_deadline(), None);
+    }
+
+    #[test]
+    fn chord_pressed_within_timeout_is_remapped() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_S, 0),
+                press(Key::KEY_D, 20),
+                release(Key::KEY_S, 100),
+                release(Key::KEY_D, 110),
+            ],
+        );
+        assert_eq!(
+            output,
+            vec![press(Key::KEY_UP, 20), release(Key::KEY_UP, 100)]
+        );
+    }
+
+    #[test]
+    fn chord_keys_pressed_too_far_apart_pass_through() {
+        let mut engine = engine(CONFIG);
+        let events = [
+            press(Key::KEY_S, 0),
+            press(Key::KEY_D, 80),
+            release(Key::KEY_S, 100),
+            release(Key::KEY_D, 110),
+        ];
+        assert_eq!(run(&mut engine, &events), events);
+    }
+
+    #[test]
+    fn chord_key_released_early_passes_through() {
+        let mut engine = engine(CONFIG);
+        let events = [press(Key::KEY_S, 0), release(Key::KEY_S, 10)];
+        assert_eq!(engine.process(events[0]), vec![]);
+        assert_eq!(engine.process(events[1]), events);
+    }
+
+    #[test]
+    fn key_before_chord_is_passed_on_and_chord_still_matches() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_A, 0),
+                press(Key::KEY_S, 10),
+                press(Key::KEY_D, 20),
+                release(Key::KEY_A, 30),
+                release(Key::KEY_D, 40),
+                release(Key::KEY_S, 50),
+            ],
+        );
+        assert_eq!(
+            output,
+            vec![
+                press(Key::KEY_A, 0),
+                press(Key::KEY_UP, 20),
+                release(Key::KEY_A, 30),
+                release(Key::KEY_UP, 40),
+            ]
+        );
+    }
+
+    #[test]
+    fn sequence_is_remapped() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_J, 0),
+                release(Key::KEY_J, 50),
+                press(Key::KEY_K, 200),
+                release(Key::KEY_K, 250),
+            ],
+        );
+        assert_eq!(
+            output,
+            vec![press(Key::KEY_ESC, 200), release(Key::KEY_ESC, 250)]
+        );
+    }
+
+    #[test]
+    fn incomplete_sequence_passes_through_after_timeout() {
+        let mut engine = engine(CONFIG);
+        let events = [press(Key::KEY_J, 0), release(Key::KEY_J, 50)];
+        assert_eq!(run(&mut engine, &events), events);
+    }
+
+    /// Index lookups per event through a stage with `count` chords of three keys, with every
+    /// key typed being part of some chord. How long each takes is timed by `benches/maps.rs`.
+    fn lookups_per_event(count: usize) -> f64 {
+        let keys: Vec<Key> = (1..=60).map(Key::new).collect();
+        let mut maps = Vec::new();
+        'chords: for (i, a) in keys.iter().enumerate() {
+            for (j, b) in keys.iter().enumerate().skip(i + 1) {
+                for c in keys.iter().skip(j + 1) {
+                    if maps.len() == count {
+                        break 'chords;
+                    }
+                    maps.push(Map {
+                        input: vec![*a, *b, *c],
+                        kind: InputKind::Chord,
+                        output: vec![Key::KEY_A],
+                        fingers: vec![],
+                    });
+                }
+            }
+        }
+        let mut stage = MapStage::new(&MappingsConfig {
+            maps: Some(maps),
+            chord_timeout_ms: 50,
+            sequence_timeout_ms: 500,
+        });
+
+        let events: Vec<KeyEvent> = (0..2_000u64)
+            .map(|i| {
+                let key = keys[(i / 2 * 7 % 60) as usize];
+                match i % 2 {
+                    0 => press(key, i * 100),
+                    _ => release(key, i * 100 + 10),
+                }
+            })
+            .collect();
+        let mut out = Vec::new();
+        for event in &events {
+            stage.timeout(event.time, &mut out);
+            stage.process(Event::Input(*event), &mut out);
+            out.clear();
+        }
+        stage.index.lookups.get() as f64 / events.len() as f64
+    }
+
+    #[test]
+    fn lookups_per_event_do_not_grow_with_number_of_maps() {
+        let small = lookups_per_event(100);
+        let large = lookups_per_event(30_000);
+        assert!(small <= 2.0, "{} lookups per event", small);
+        assert_eq!(small, large);
+    }
+
+    #[test]
+    fn single_key_is_remapped_to_combination() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[press(Key::KEY_CAPSLOCK, 0), release(Key::KEY_CAPSLOCK, 10)],
+        );
+        assert_eq!(
+            output,
+            vec![
+                press(Key::KEY_LEFTCTRL, 0),
+                press(Key::KEY_A, 0),
+                release(Key::KEY_A, 10),
+                release(Key::KEY_LEFTCTRL, 10),
+            ]
+        );
+    }
+}
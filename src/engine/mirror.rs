@@ -0,0 +1,257 @@
+// One-handed typing: while the mirror key is held, each key types the key pressed by the same
+// finger of the other hand. Tapping the mirror key on its own types it as usual.
+
+use std::time::Duration;
+
+use super::{Event, Stage};
+use crate::config::schema::MirrorConfig;
+use crate::key::{KeyEvent, KeyState};
+use crate::position::Positions;
+use crate::Key;
+
+/// The mirror key while it's down.
+struct Held {
+    press: KeyEvent,
+    /// Whether another key was pressed while it was down, in which case it isn't tapped.
+    used: bool,
+}
+
+pub struct MirrorStage {
+    key: Key,
+    pairs: Vec<(Key, Key)>,
+    tapping_term: Duration,
+    held: Option<Held>,
+    /// Keys pressed while the mirror key was held, with the key they were mirrored to. They stay
+    /// mirrored until released, even if the mirror key is released first.
+    mirrored: Vec<(Key, Key)>,
+}
+
+impl MirrorStage {
+    pub fn new(config: &MirrorConfig, positions: &Positions) -> MirrorStage {
+        MirrorStage {
+            key: config.key,
+            pairs: positions
+                .mirror_pairs()
+                .into_iter()
+                .filter(|(from, to)| *from != config.key && *to != config.key)
+                .collect(),
+            tapping_term: Duration::from_millis(config.tapping_term_ms),
+            held: None,
+            mirrored: Vec::new(),
+        }
+    }
+
+    fn mirror_of(&self, key: Key) -> Option<Key> {
+        self.pairs
+            .iter()
+            .find(|(from, _)| *from == key)
+            .map(|(_, to)| *to)
+    }
+
+    fn process_mirror_key(&mut self, input: KeyEvent, out: &mut Vec<Event>) {
+        match input.state {
+            KeyState::Pressed if self.held.is_none() => {
+                self.held = Some(Held {
+                    press: input,
+                    used: false,
+                })
+            }
+            KeyState::Released => {
+                if let Some(held) = self.held.take() {
+                    if !held.used && input.time <= held.press.time + self.tapping_term {
+                        out.push(Event::Input(held.press));
+                        out.push(Event::Input(input));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Stage for MirrorStage {
+    fn process(&mut self, event: Event, out: &mut Vec<Event>) {
+        let input = match event {
+            Event::Input(input) => input,
+            Event::Output(_) => return out.push(event),
+        };
+        if input.key == self.key {
+            return self.process_mirror_key(input, out);
+        }
+
+        if let Some(index) = self.mirrored.iter().position(|(key, _)| *key == input.key) {
+            let (_, mirror) = self.mirrored[index];
+            if input.is_release() {
+                self.mirrored.remove(index);
+            }
+            return out.push(Event::Output(KeyEvent {
+                key: mirror,
+                ..input
+            }));
+        }
+
+        match &mut self.held {
+            Some(held) if input.is_press() => {
+                held.used = true;
+                match self.mirror_of(input.key) {
+                    Some(mirror) => {
+                        self.mirrored.push((input.key, mirror));
+                        out.push(Event::Output(KeyEvent {
+                            key: mirror,
+                            ..input
+                        }));
+                    }
+                    None => out.push(event),
+                }
+            }
+            _ => out.push(event),
+        }
+    }
+
+    fn timeout(&mut self, _now: Duration, _out: &mut Vec<Event>) {}
+
+    fn next_deadline(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test_mirror_stage {
+    use super::super::test_utils::*;
+    use crate::Key;
+
+    const CONFIG: &str = r#"
+[position]
+rows = [
+    ["q", "w", "e", "r", "t", "y", "u", "i", "o", "p"],
+    ["a", "s", "d", "f", "g", "h", "j", "k", "l", "semicolon"],
+]
+
+[position.left_hand]
+thumb = "space"
+index = ["f", "g", "r", "t"]
+middle = ["d", "e"]
+
+[position.right_hand]
+index = ["j", "h", "u", "y"]
+middle = ["k", "i"]
+
+[mirror]
+key = "space"
+tapping_term_ms = 200
+"#;
+
+    #[test]
+    fn keys_are_mirrored_while_mirror_key_is_held() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_SPACE, 0),
+                press(Key::KEY_F, 100),
+                release(Key::KEY_F, 150),
+                press(Key::KEY_E, 200),
+                release(Key::KEY_E, 250),
+                release(Key::KEY_SPACE, 300),
+            ],
+        );
+        assert_eq!(
+            output,
+            vec![
+                press(Key::KEY_J, 100),
+                release(Key::KEY_J, 150),
+                press(Key::KEY_I, 200),
+                release(Key::KEY_I, 250),
+            ]
+        );
+    }
+
+    #[test]
+    fn outer_keys_of_a_finger_are_mirrored_to_outer_keys() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_SPACE, 0),
+                press(Key::KEY_T, 10),
+                release(Key::KEY_T, 20),
+                press(Key::KEY_R, 30),
+                release(Key::KEY_R, 40),
+                release(Key::KEY_SPACE, 50),
+            ],
+        );
+        assert_eq!(
+            output,
+            vec![
+                press(Key::KEY_Y, 10),
+                release(Key::KEY_Y, 20),
+                press(Key::KEY_U, 30),
+                release(Key::KEY_U, 40),
+            ]
+        );
+    }
+
+    #[test]
+    fn tapping_mirror_key_types_it() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[press(Key::KEY_SPACE, 0), release(Key::KEY_SPACE, 50)],
+        );
+        assert_eq!(
+            output,
+            vec![press(Key::KEY_SPACE, 0), release(Key::KEY_SPACE, 50)]
+        );
+    }
+
+    #[test]
+    fn holding_mirror_key_past_tapping_term_types_nothing() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[press(Key::KEY_SPACE, 0), release(Key::KEY_SPACE, 500)],
+        );
+        assert_eq!(output, vec![]);
+    }
+
+    #[test]
+    fn mirrored_key_is_released_after_mirror_key() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_SPACE, 0),
+                press(Key::KEY_J, 10),
+                release(Key::KEY_SPACE, 20),
+                release(Key::KEY_J, 30),
+            ],
+        );
+        assert_eq!(output, vec![press(Key::KEY_F, 10), release(Key::KEY_F, 30)]);
+    }
+
+    #[test]
+    fn keys_without_a_mirror_pass_through() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_SPACE, 0),
+                press(Key::KEY_Z, 10),
+                release(Key::KEY_Z, 20),
+                release(Key::KEY_SPACE, 30),
+            ],
+        );
+        assert_eq!(output, vec![press(Key::KEY_Z, 10), release(Key::KEY_Z, 20)]);
+    }
+
+    #[test]
+    fn mirror_needs_keys_on_both_hands() {
+        let result = crate::config::parsing::parse_config(
+            r#"
+[mirror]
+key = "space"
+"#,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,389 @@
+// The engine turns the key events read from the remapped devices into the key events to write
+// to the virtual device. Events pass through a series of stages, each of which may hold events
+// back until it can decide what they mean, e.g. whether a key is the start of a chord.
+//
+// Events are timed by their kernel timestamps, and timers expire by the engine's clock: the
+// clock the kernel timestamps events with, or a virtual clock, so that the engine runs the same
+// in virtual time.
+
+mod escape;
+mod index;
+mod maps;
+mod mirror;
+mod tap_hold;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::clock::{Clock, MonotonicClock};
+use crate::config::schema::Config;
+use crate::key::{KeyEvent, KeySet, KeyState};
+use crate::Key;
+
+use self::escape::EscapeChord;
+use self::maps::MapStage;
+use self::mirror::MirrorStage;
+use self::tap_hold::TapHoldStage;
+
+/// An event passing between the stages of the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A key event which later stages may still remap.
+    Input(KeyEvent),
+    /// A key event which has already been remapped, and is passed on to the output as is.
+    Output(KeyEvent),
+}
+
+trait Stage {
+    /// Handle `event`, pushing any events which can now be passed on to `out`.
+    fn process(&mut self, event: Event, out: &mut Vec<Event>);
+
+    /// Handle the timer set by `next_deadline` expiring at `now`.
+    fn timeout(&mut self, now: Duration, out: &mut Vec<Event>);
+
+    /// When the stage next needs `timeout` to be called, if it's waiting on a timer.
+    fn next_deadline(&self) -> Option<Duration>;
+}
+
+pub struct Engine<C: Clock = MonotonicClock> {
+    clock: C,
+    /// Kept to rebuild the stages from, when resyncing.
+    config: Config,
+    stages: Vec<Box<dyn Stage>>,
+    /// How many times each output key is currently pressed, as more than one map may hold the
+    /// same key, e.g. a modifier.
+    pressed: BTreeMap<Key, usize>,
+    escape_chord: EscapeChord,
+}
+
+impl Engine {
+    pub fn new(config: &Config) -> Engine {
+        Engine::with_clock(config, MonotonicClock)
+    }
+}
+
+impl<C: Clock> Engine<C> {
+    /// An engine whose timers expire by `clock`.
+    pub fn with_clock(config: &Config, clock: C) -> Engine<C> {
+        Engine {
+            clock,
+            config: config.clone(),
+            stages: stages(config),
+            pressed: BTreeMap::new(),
+            escape_chord: EscapeChord::new(&config.escape_chord),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Switch to `config`, e.g. after the config file changed. The output keys held down are
+    /// still known, so the next `resync` releases those which the new config doesn't hold.
+    pub fn reload(&mut self, config: &Config) {
+        self.config = config.clone();
+        self.stages = stages(config);
+        self.escape_chord = EscapeChord::new(&config.escape_chord);
+    }
+
+    /// Whether `event` completes the escape chord, meaning remapping should stop at once. This
+    /// must be checked before the event is processed.
+    pub fn escape_pressed(&mut self, event: &KeyEvent) -> bool {
+        self.escape_chord.process(event)
+    }
+
+    /// Process an input event, returning the events to output.
+    ///
+    /// Any timers which expired before the event happened are handled first.
+    pub fn process(&mut self, event: KeyEvent) -> Vec<KeyEvent> {
+        let mut output = self.expire_timers(event.time);
+        output.extend(self.run_stages(0, vec![Event::Input(event)]));
+        output
+    }
+
+    /// Handle every timer which has expired by now on the clock, returning the events to output.
+    pub fn timeout(&mut self) -> Vec<KeyEvent> {
+        self.expire_timers(self.clock.now())
+    }
+
+    /// Handle every timer which has expired by `now`, returning the events to output.
+    fn expire_timers(&mut self, now: Duration) -> Vec<KeyEvent> {
+        let mut output = Vec::new();
+        // Timers are handled in order, as events from one stage may set timers in later ones.
+        while let Some((index, deadline)) = self
+            .stages
+            .iter()
+            .enumerate()
+            .filter_map(|(index, stage)| Some((index, stage.next_deadline()?)))
+            .filter(|(_, deadline)| *deadline <= now)
+            .min_by_key(|(_, deadline)| *deadline)
+        {
+            let mut events = Vec::new();
+            self.stages[index].timeout(deadline, &mut events);
+            output.extend(self.run_stages(index + 1, events));
+        }
+        output
+    }
+
+    /// Bring the engine back in line with the keys `held` down on the devices now, which events
+    /// may not have told of, returning the events which make the output match. The held keys are
+    /// taken as just pressed, output keys which they don't hold down are released, and whatever
+    /// the stages were waiting for is forgotten.
+    pub fn resync(&mut self, held: &KeySet) -> Vec<KeyEvent> {
+        let now = self.clock.now();
+        let output_before: KeySet = self.pressed.keys().copied().collect();
+        self.stages = stages(&self.config);
+        self.pressed.clear();
+        self.escape_chord.resync(held);
+
+        let presses = held
+            .iter()
+            .map(|key| Event::Input(KeyEvent::new(key, KeyState::Pressed, now)))
+            .collect();
+        let pressed = self.run_stages(0, presses);
+        let mut output: Vec<KeyEvent> = output_before
+            .iter()
+            .filter(|key| !self.pressed.contains_key(key))
+            .map(|key| KeyEvent::new(key, KeyState::Released, now))
+            .collect();
+        // Output keys which were down already are left down.
+        output.extend(
+            pressed
+                .into_iter()
+                .filter(|event| !(event.is_press() && output_before.contains(event.key))),
+        );
+        output
+    }
+
+    /// When `timeout` next needs to be called.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.stages
+            .iter()
+            .filter_map(|stage| stage.next_deadline())
+            .min()
+    }
+
+    fn run_stages(&mut self, first_stage: usize, mut events: Vec<Event>) -> Vec<KeyEvent> {
+        for stage in self.stages.iter_mut().skip(first_stage) {
+            let mut next_events = Vec::new();
+            for event in events {
+                stage.process(event, &mut next_events);
+            }
+            events = next_events;
+        }
+        events
+            .into_iter()
+            .map(|event| match event {
+                Event::Input(event) | Event::Output(event) => event,
+            })
+            .filter(|event| self.track_output(event))
+            .collect()
+    }
+
+    /// Keep count of pressed output keys, returning whether `event` changes the output.
+    fn track_output(&mut self, event: &KeyEvent) -> bool {
+        let count = self.pressed.entry(event.key).or_insert(0);
+        let changes_output = match event.state {
+            KeyState::Pressed => {
+                *count += 1;
+                *count == 1
+            }
+            // The key isn't down on the output, e.g. it was pressed before the engine started.
+            KeyState::Released if *count == 0 => false,
+            KeyState::Released => {
+                *count -= 1;
+                *count == 0
+            }
+            KeyState::Repeated => *count > 0,
+        };
+        if *count == 0 {
+            self.pressed.remove(&event.key);
+        }
+        changes_output
+    }
+}
+
+/// The stages `config` calls for, in the order events pass through them.
+fn stages(config: &Config) -> Vec<Box<dyn Stage>> {
+    let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+    if let Some(mappings) = &config.mappings {
+        stages.push(Box::new(MapStage::new(mappings)));
+    }
+    if let Some(home_row_mods) = &config.home_row_mods {
+        stages.push(Box::new(TapHoldStage::new(
+            home_row_mods,
+            config.position.clone(),
+        )));
+    }
+    if let Some(mirror) = &config.mirror {
+        stages.push(Box::new(MirrorStage::new(mirror, &config.position)));
+    }
+    stages
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::config::parsing::parse_config;
+
+    pub fn ms(milliseconds: u64) -> Duration {
+        Duration::from_millis(milliseconds)
+    }
+
+    pub fn press(key: Key, milliseconds: u64) -> KeyEvent {
+        KeyEvent::new(key, KeyState::Pressed, ms(milliseconds))
+    }
+
+    pub fn release(key: Key, milliseconds: u64) -> KeyEvent {
+        KeyEvent::new(key, KeyState::Released, ms(milliseconds))
+    }
+
+    /// An engine running in virtual time.
+    pub fn engine(config: &str) -> Engine<VirtualClock> {
+        engine_on(config, VirtualClock::default())
+    }
+
+    /// An engine running in the virtual time of `clock`, e.g. that of a scripted source.
+    pub fn engine_on(config: &str, clock: VirtualClock) -> Engine<VirtualClock> {
+        Engine::with_clock(&parse_config(config).unwrap(), clock)
+    }
+
+    /// Process `events` then any remaining timers, returning every output event.
+    pub fn run(engine: &mut Engine<VirtualClock>, events: &[KeyEvent]) -> Vec<KeyEvent> {
+        let mut output = Vec::new();
+        for event in events {
+            engine.clock().advance_to(event.time);
+            output.extend(engine.process(*event));
+        }
+        if let Some(deadline) = engine.next_deadline() {
+            engine.clock().advance_to(deadline + ms(10_000));
+            output.extend(engine.timeout());
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod test_engine {
+    use super::test_utils::*;
+    use super::*;
+    use crate::config::parsing::parse_config;
+
+    #[test]
+    fn keys_pass_through_without_config() {
+        let mut engine = engine("");
+        let events = [press(Key::KEY_A, 0), release(Key::KEY_A, 10)];
+        assert_eq!(run(&mut engine, &events), events);
+    }
+
+    #[test]
+    fn output_key_held_twice_is_only_released_once_both_are_released() {
+        let mut engine = engine(
+            r#"
+[mappings.maps]
+a = "lshift"
+b = "lshift"
+"#,
+        );
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_A, 0),
+                press(Key::KEY_B, 10),
+                release(Key::KEY_A, 20),
+                release(Key::KEY_B, 30),
+            ],
+        );
+        assert_eq!(
+            output,
+            vec![
+                press(Key::KEY_LEFTSHIFT, 0),
+                release(Key::KEY_LEFTSHIFT, 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn resync_releases_output_keys_no_longer_held() {
+        let mut engine = engine(
+            r#"
+[mappings.maps]
+a = "lshift"
+"#,
+        );
+        assert_eq!(
+            engine.process(press(Key::KEY_A, 0)),
+            vec![press(Key::KEY_LEFTSHIFT, 0)]
+        );
+        engine.clock().advance_to(ms(100));
+        assert_eq!(
+            engine.resync(&KeySet::default()),
+            vec![release(Key::KEY_LEFTSHIFT, 100)]
+        );
+        // The release of the key, if it comes after all, changes nothing.
+        assert_eq!(run(&mut engine, &[release(Key::KEY_A, 200)]), vec![]);
+    }
+
+    #[test]
+    fn resync_presses_held_keys_as_if_just_pressed() {
+        let mut engine = engine(
+            r#"
+[mappings.maps]
+a = "lshift"
+"#,
+        );
+        let held = KeySet::from_iter([Key::KEY_A, Key::KEY_B]);
+        engine.clock().advance_to(ms(100));
+        assert_eq!(
+            engine.resync(&held),
+            vec![press(Key::KEY_LEFTSHIFT, 100), press(Key::KEY_B, 100)]
+        );
+        assert_eq!(
+            run(&mut engine, &[release(Key::KEY_A, 200)]),
+            vec![release(Key::KEY_LEFTSHIFT, 200)]
+        );
+    }
+
+    #[test]
+    fn resync_leaves_output_keys_still_held_down() {
+        let mut engine = engine(
+            r#"
+[mappings.maps]
+a = "lshift"
+"#,
+        );
+        engine.process(press(Key::KEY_A, 0));
+        engine.clock().advance_to(ms(100));
+        assert_eq!(engine.resync(&KeySet::from_iter([Key::KEY_A])), vec![]);
+    }
+
+    #[test]
+    fn resync_after_reload_remaps_held_keys_by_the_new_config() {
+        let mut engine = engine(
+            r#"
+[mappings.maps]
+a = "lshift"
+"#,
+        );
+        engine.process(press(Key::KEY_A, 0));
+        engine.reload(
+            &parse_config(
+                r#"
+[mappings.maps]
+a = "lctrl"
+"#,
+            )
+            .unwrap(),
+        );
+        engine.clock().advance_to(ms(100));
+        assert_eq!(
+            engine.resync(&KeySet::from_iter([Key::KEY_A])),
+            vec![
+                release(Key::KEY_LEFTSHIFT, 100),
+                press(Key::KEY_LEFTCTRL, 100)
+            ]
+        );
+    }
+}
@@ -0,0 +1,299 @@
+// Home row modifiers: keys which type as usual when tapped, but act as a modifier when held.
+//
+// To avoid misfires while typing quickly, a key only counts as held if it's held past the
+// tapping term, or if the next key pressed is on the opposite hand. Rolling onto another key on
+// the same hand always types both keys.
+
+use std::time::Duration;
+
+use super::{Event, Stage};
+use crate::config::schema::HomeRowModsConfig;
+use crate::key::{KeyEvent, KeyState};
+use crate::position::Positions;
+use crate::Key;
+
+/// A tap-hold key which is down, but not yet known to be tapped or held.
+struct Undecided {
+    press: KeyEvent,
+    modifier: Key,
+}
+
+pub struct TapHoldStage {
+    /// Each tap-hold key with the modifier it acts as when held.
+    modifiers: Vec<(Key, Key)>,
+    positions: Positions,
+    tapping_term: Duration,
+    undecided: Option<Undecided>,
+    /// Keys which were decided to be held, with their modifier.
+    held: Vec<(Key, Key)>,
+}
+
+impl TapHoldStage {
+    pub fn new(config: &HomeRowModsConfig, positions: Positions) -> TapHoldStage {
+        TapHoldStage {
+            modifiers: config.modifiers.clone(),
+            positions,
+            tapping_term: Duration::from_millis(config.tapping_term_ms),
+            undecided: None,
+            held: Vec::new(),
+        }
+    }
+
+    fn modifier(&self, key: Key) -> Option<Key> {
+        self.modifiers
+            .iter()
+            .find(|(tap_hold_key, _)| *tap_hold_key == key)
+            .map(|(_, modifier)| *modifier)
+    }
+
+    /// Whether pressing `key` while `undecided` is down means `undecided` is being held.
+    fn is_opposite_hand(&self, undecided: &Undecided, key: Key) -> bool {
+        match (
+            self.positions.hand_of(undecided.press.key),
+            self.positions.hand_of(key),
+        ) {
+            (Some(hand), Some(other_hand)) => hand != other_hand,
+            // Keys which aren't assigned to a hand, e.g. ones off the main block, can be used
+            // with any modifier.
+            _ => true,
+        }
+    }
+
+    fn tap(&mut self, out: &mut Vec<Event>) {
+        if let Some(undecided) = self.undecided.take() {
+            out.push(Event::Input(undecided.press));
+        }
+    }
+
+    fn hold(&mut self, time: Duration, out: &mut Vec<Event>) {
+        if let Some(undecided) = self.undecided.take() {
+            out.push(Event::Output(KeyEvent::new(
+                undecided.modifier,
+                KeyState::Pressed,
+                time,
+            )));
+            self.held.push((undecided.press.key, undecided.modifier));
+        }
+    }
+
+    fn process_decided(&mut self, event: Event, out: &mut Vec<Event>) {
+        let input = match event {
+            Event::Input(input) => input,
+            Event::Output(_) => return out.push(event),
+        };
+
+        if let Some(index) = self.held.iter().position(|(key, _)| *key == input.key) {
+            if input.is_release() {
+                let (_, modifier) = self.held.remove(index);
+                out.push(Event::Output(KeyEvent::new(
+                    modifier,
+                    KeyState::Released,
+                    input.time,
+                )));
+            }
+            return;
+        }
+
+        match self.modifier(input.key) {
+            Some(modifier) if input.is_press() => {
+                self.undecided = Some(Undecided {
+                    press: input,
+                    modifier,
+                })
+            }
+            _ => out.push(event),
+        }
+    }
+}
+
+impl Stage for TapHoldStage {
+    fn process(&mut self, event: Event, out: &mut Vec<Event>) {
+        let undecided = match &self.undecided {
+            None => return self.process_decided(event, out),
+            Some(undecided) => undecided,
+        };
+
+        match event {
+            Event::Input(input) if input.key == undecided.press.key => {
+                if input.is_release() {
+                    self.tap(out);
+                    out.push(event);
+                }
+            }
+            Event::Input(input) if input.is_press() => {
+                if self.is_opposite_hand(undecided, input.key) {
+                    self.hold(input.time, out);
+                } else {
+                    self.tap(out);
+                }
+                self.process_decided(event, out);
+            }
+            Event::Output(output) if output.is_press() => {
+                self.hold(output.time, out);
+                self.process_decided(event, out);
+            }
+            // Releasing a key pressed before the tap-hold key is most likely the end of a roll,
+            // so the tap-hold key is tapped to keep the order of the two keys.
+            _ => {
+                self.tap(out);
+                self.process_decided(event, out);
+            }
+        }
+    }
+
+    fn timeout(&mut self, now: Duration, out: &mut Vec<Event>) {
+        self.hold(now, out);
+    }
+
+    fn next_deadline(&self) -> Option<Duration> {
+        self.undecided
+            .as_ref()
+            .map(|undecided| undecided.press.time + self.tapping_term)
+    }
+}
+
+#[cfg(test)]
+mod test_tap_hold_stage {
+    use super::super::test_utils::*;
+    use crate::Key;
+
+    const CONFIG: &str = r#"
+[position.left_hand]
+ring = "s"
+middle = "d"
+index = "f"
+
+[position.right_hand]
+index = "j"
+middle = "k"
+
+[home_row_mods]
+tapping_term_ms = 200
+
+[home_row_mods.modifiers]
+d = "lctrl"
+f = "lshift"
+k = "rctrl"
+"#;
+
+    #[test]
+    fn tap_types_the_key() {
+        let mut engine = engine(CONFIG);
+        let events = [press(Key::KEY_F, 0), release(Key::KEY_F, 50)];
+        assert_eq!(engine.process(events[0]), vec![]);
+        assert_eq!(engine.process(events[1]), events);
+    }
+
+    #[test]
+    fn holding_past_tapping_term_acts_as_modifier() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_F, 0),
+                press(Key::KEY_S, 300),
+                release(Key::KEY_S, 310),
+                release(Key::KEY_F, 400),
+            ],
+        );
+        assert_eq!(
+            output,
+            vec![
+                press(Key::KEY_LEFTSHIFT, 200),
+                press(Key::KEY_S, 300),
+                release(Key::KEY_S, 310),
+                release(Key::KEY_LEFTSHIFT, 400),
+            ]
+        );
+    }
+
+    #[test]
+    fn hold_is_decided_once_the_clock_passes_tapping_term() {
+        let mut engine = engine(CONFIG);
+        assert_eq!(engine.process(press(Key::KEY_F, 0)), vec![]);
+        engine.clock().advance_to(ms(199));
+        assert_eq!(engine.timeout(), vec![]);
+        engine.clock().advance_to(ms(250));
+        assert_eq!(engine.timeout(), vec![press(Key::KEY_LEFTSHIFT, 200)]);
+    }
+
+    #[test]
+    fn key_on_opposite_hand_makes_it_a_modifier() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_F, 0),
+                press(Key::KEY_J, 50),
+                release(Key::KEY_J, 60),
+                release(Key::KEY_F, 70),
+            ],
+        );
+        assert_eq!(
+            output,
+            vec![
+                press(Key::KEY_LEFTSHIFT, 50),
+                press(Key::KEY_J, 50),
+                release(Key::KEY_J, 60),
+                release(Key::KEY_LEFTSHIFT, 70),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_on_same_hand_types_both_keys() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_F, 0),
+                press(Key::KEY_S, 50),
+                release(Key::KEY_F, 60),
+                release(Key::KEY_S, 70),
+            ],
+        );
+        assert_eq!(
+            output,
+            vec![
+                press(Key::KEY_F, 0),
+                press(Key::KEY_S, 50),
+                release(Key::KEY_F, 60),
+                release(Key::KEY_S, 70),
+            ]
+        );
+    }
+
+    #[test]
+    fn roll_between_tap_hold_keys_on_same_hand_types_both() {
+        let mut engine = engine(CONFIG);
+        let output = run(
+            &mut engine,
+            &[
+                press(Key::KEY_D, 0),
+                press(Key::KEY_F, 30),
+                release(Key::KEY_D, 60),
+                release(Key::KEY_F, 90),
+            ],
+        );
+        assert_eq!(
+            output,
+            vec![
+                press(Key::KEY_D, 0),
+                press(Key::KEY_F, 30),
+                release(Key::KEY_D, 60),
+                release(Key::KEY_F, 90),
+            ]
+        );
+    }
+
+    #[test]
+    fn home_row_mod_keys_must_have_a_hand() {
+        let result = crate::config::parsing::parse_config(
+            r#"
+[home_row_mods.modifiers]
+a = "lmeta"
+"#,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -1,5 +1,4 @@
 use thiserror::Error;
-use toml;
 
 /// Main error type of the program, transparently handles all other error types.
 #[derive(Debug, Error)]
@@ -18,10 +17,14 @@ pub enum Error {
 
     #[error(transparent)]
     ConfigError(#[from] ConfigError),
+
+    #[error(transparent)]
+    TraceError(#[from] TraceError),
 }
 
 #[derive(Debug, Error)]
 pub enum VirtualDeviceCreationError {
+    #[allow(dead_code)]
     #[error("{0}")]
     Message(String),
 
@@ -66,6 +69,16 @@ pub enum ConfigError {
 
     #[error("{0}")]
     DeserializeError(String),
+
+    #[error("{0}")]
+    SerializeError(String),
+}
+
+#[derive(Debug, Error)]
+#[error("Line {line} of trace: {message}")]
+pub struct TraceError {
+    pub line: usize,
+    pub message: String,
 }
 
 impl From<toml::de::Error> for ConfigError {
@@ -73,3 +86,9 @@ impl From<toml::de::Error> for ConfigError {
         ConfigError::DeserializeError(value.message().to_owned())
     }
 }
+
+impl From<toml::ser::Error> for ConfigError {
+    fn from(value: toml::ser::Error) -> Self {
+        ConfigError::SerializeError(value.to_string())
+    }
+}
@@ -1 +1,152 @@
-pub type Key = evdev::Key;
+use std::time::Duration;
+
+pub type Key = evdev::KeyCode;
+
+/// Number of key codes known to the kernel (KEY_CNT in linux/input-event-codes.h).
+pub const KEY_CODE_COUNT: u16 = 0x300;
+
+/// The value of a key event, as in the kernel's `input_event`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyState {
+    Released,
+    Pressed,
+    Repeated,
+}
+
+impl KeyState {
+    pub fn from_value(value: i32) -> Option<KeyState> {
+        match value {
+            0 => Some(KeyState::Released),
+            1 => Some(KeyState::Pressed),
+            2 => Some(KeyState::Repeated),
+            _ => None,
+        }
+    }
+
+    pub fn value(self) -> i32 {
+        match self {
+            KeyState::Released => 0,
+            KeyState::Pressed => 1,
+            KeyState::Repeated => 2,
+        }
+    }
+}
+
+/// A key being pressed, released or repeated at `time`, measured from an arbitrary origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub state: KeyState,
+    pub time: Duration,
+}
+
+impl KeyEvent {
+    pub fn new(key: Key, state: KeyState, time: Duration) -> KeyEvent {
+        KeyEvent { key, state, time }
+    }
+
+    pub fn is_press(&self) -> bool {
+        self.state == KeyState::Pressed
+    }
+
+    pub fn is_release(&self) -> bool {
+        self.state == KeyState::Released
+    }
+}
+
+/// A set of keys, as a fixed-size bitset of key codes, so it can be hashed and compared in
+/// constant time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct KeySet([u64; KEY_CODE_COUNT as usize / 64]);
+
+impl KeySet {
+    /// Add `key`, returning whether it wasn't in the set already. Keys with codes beyond
+    /// `KEY_CODE_COUNT` can't be added.
+    pub fn insert(&mut self, key: Key) -> bool {
+        let (word, bit) = KeySet::position(key);
+        match self.0.get_mut(word) {
+            Some(bits) if *bits & bit == 0 => {
+                *bits |= bit;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove `key`, returning whether it was in the set.
+    pub fn remove(&mut self, key: Key) -> bool {
+        let contained = self.contains(key);
+        let (word, bit) = KeySet::position(key);
+        if let Some(bits) = self.0.get_mut(word) {
+            *bits &= !bit;
+        }
+        contained
+    }
+
+    pub fn contains(&self, key: Key) -> bool {
+        let (word, bit) = KeySet::position(key);
+        self.0.get(word).is_some_and(|bits| bits & bit != 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|bits| *bits == 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Key> + '_ {
+        self.0.iter().enumerate().flat_map(|(word, bits)| {
+            (0..64)
+                .filter(move |bit| bits & (1 << bit) != 0)
+                .map(move |bit| Key::new((word * 64 + bit) as u16))
+        })
+    }
+
+    fn position(key: Key) -> (usize, u64) {
+        let code = key.code() as usize;
+        (code / 64, 1 << (code % 64))
+    }
+}
+
+impl FromIterator<Key> for KeySet {
+    fn from_iter<T: IntoIterator<Item = Key>>(keys: T) -> KeySet {
+        let mut set = KeySet::default();
+        for key in keys {
+            set.insert(key);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod test_key_set {
+    use super::*;
+
+    #[test]
+    fn keys_are_added_once() {
+        let mut set = KeySet::default();
+        assert!(set.insert(Key::KEY_A));
+        assert!(!set.insert(Key::KEY_A));
+        assert!(set.insert(Key::new(KEY_CODE_COUNT - 1)));
+        assert!(set.contains(Key::KEY_A));
+        assert!(!set.contains(Key::KEY_B));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn order_does_not_matter() {
+        assert_eq!(
+            KeySet::from_iter([Key::KEY_S, Key::KEY_D]),
+            KeySet::from_iter([Key::KEY_D, Key::KEY_S])
+        );
+    }
+
+    #[test]
+    fn keys_beyond_code_count_are_not_added() {
+        let mut set = KeySet::default();
+        assert!(!set.insert(Key::new(KEY_CODE_COUNT)));
+        assert!(set.is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+// Keeping keystrokes out of the logs. The remapper sees everything typed, passwords included, so
+// log messages only say how many keys or events there were, unless `log_keys` is set in the
+// config. Keys are only ever logged through `Keys` and `Events`, which apply this.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::key::KeyEvent;
+use crate::trace::format_event;
+use crate::Key;
+
+static LOG_KEYS: AtomicBool = AtomicBool::new(false);
+
+/// Set whether keys are written in log messages, warning loudly if they are.
+pub fn set_log_keys(log_keys: bool) {
+    if log_keys && !LOG_KEYS.load(Ordering::Relaxed) {
+        eprintln!("**************************************************************************");
+        eprintln!("WARNING: log_keys is set, so every key typed, passwords included, may be");
+        eprintln!("written to the logs. Only use this for debugging, and delete the logs after.");
+        eprintln!("**************************************************************************");
+    }
+    LOG_KEYS.store(log_keys, Ordering::Relaxed);
+}
+
+/// `items` formatted as a list if logging keys, otherwise just how many there are.
+fn format_list<T>(
+    items: &[T],
+    noun: &str,
+    log_keys: bool,
+    format: impl Fn(&T) -> String,
+) -> String {
+    match log_keys {
+        true => format!(
+            "[{}]",
+            items.iter().map(format).collect::<Vec<_>>().join(", ")
+        ),
+        false => format!("<{} {}>", items.len(), noun),
+    }
+}
+
+/// Keys to log, redacted to how many there are unless logging keys.
+pub struct Keys(pub Vec<Key>);
+
+/// Events to log, redacted to how many there are unless logging keys.
+pub struct Events<'a>(pub &'a [KeyEvent]);
+
+impl Keys {
+    fn format(&self, log_keys: bool) -> String {
+        format_list(&self.0, "keys", log_keys, |key| format!("{:?}", key))
+    }
+}
+
+impl Events<'_> {
+    fn format(&self, log_keys: bool) -> String {
+        format_list(self.0, "events", log_keys, format_event)
+    }
+}
+
+impl fmt::Display for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.format(LOG_KEYS.load(Ordering::Relaxed)))
+    }
+}
+
+impl fmt::Display for Events<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.format(LOG_KEYS.load(Ordering::Relaxed)))
+    }
+}
+
+// The global setting is shared between tests, so they format with `log_keys` given instead.
+#[cfg(test)]
+mod test_redaction {
+    use super::*;
+    use crate::engine::test_utils::{press, release};
+
+    #[test]
+    fn keys_are_only_counted_by_default() {
+        let keys = Keys(vec![Key::KEY_P, Key::KEY_W]);
+        assert_eq!(keys.format(false), "<2 keys>");
+        let events = [press(Key::KEY_P, 0), release(Key::KEY_P, 10)];
+        assert_eq!(Events(&events).format(false), "<2 events>");
+    }
+
+    #[test]
+    fn keys_are_written_when_asked_for() {
+        let keys = Keys(vec![Key::KEY_P, Key::KEY_W]);
+        assert_eq!(keys.format(true), "[KEY_P, KEY_W]");
+        let events = [press(Key::KEY_P, 0), release(Key::KEY_P, 10)];
+        assert_eq!(Events(&events).format(true), "[+KEY_P @0ms, -KEY_P @10ms]");
+    }
+}
@@ -1,22 +1,93 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use clap::{Args, Parser, Subcommand};
 use errors::Error;
 
-use crate::device::{get_all_devices, DeviceInfo};
+use crate::device::event_loop::EvdevSource;
+use crate::device::events::{EvdevSink, ReleasingSink};
+use crate::device::opener::DeviceOpener;
+use crate::device::{get_all_devices, DeviceInfo, VirtualDevice};
+use crate::engine::Engine;
+use crate::remapper::Stop;
 
 mod auxiliary;
+mod clock;
+mod commands;
 mod config;
 mod device;
+mod engine;
 mod errors;
 mod key;
+mod logging;
 mod mapping;
+mod position;
+mod privileges;
+mod remapper;
+mod trace;
 
 pub use crate::key::Key;
 
-fn print_devices(devices: &Vec<impl DeviceInfo>) {
-    for device in devices {
-        println!("'{}'", device.to_string());
-    }
+/// Contains "virtual", so the remapper's own output is never picked as a keyboard to remap.
+const VIRTUAL_KEYBOARD_NAME: &str = "Chorded Key Remapper virtual keyboard";
+
+#[derive(Parser)]
+#[command(version, about = "Remap chords of keys on a keyboard to other keys")]
+// Options for remapping can't be given with a subcommand, whose own options go after it.
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// Path of the config file.
+    #[arg(short, long, global = true, default_value = "config.toml")]
+    config: PathBuf,
+
+    #[command(flatten)]
+    selection: SelectionArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Options for the commands which select devices from the config.
+#[derive(Args)]
+struct SelectionArgs {
+    /// Explain why each device is selected or not.
+    #[arg(long)]
+    explain: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check the config file, and score how comfortable each chord is to play.
+    CheckConfig,
+    /// Check the remapper can access the input devices and uinput without root, and print how
+    /// to fix it if not.
+    Doctor,
+    /// List the input devices, what kind each is, and whether the config selects it.
+    ///
+    /// Whether another program has grabbed a selected device is found by grabbing it for a
+    /// moment, during which its events don't reach other programs.
+    ListDevices {
+        /// Print the devices as JSON, for scripts.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write every key event from the selected devices to a trace file, until stopped.
+    Record {
+        /// Trace file to write.
+        output: PathBuf,
+        /// Grab the devices and pass their events on through a virtual device.
+        #[arg(long)]
+        grab: bool,
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+    /// Feed a trace of key events through the remapper, and print the events it outputs.
+    Replay {
+        /// Trace file, with one event per line, e.g. "+KEY_S @0ms" or "-KEY_S @80ms".
+        trace: PathBuf,
+        /// Compare the output to this trace instead of printing it, failing if they differ.
+        #[arg(long)]
+        expect: Option<PathBuf>,
+    },
 }
 
 extern crate env_logger;
@@ -24,24 +95,84 @@ extern crate log;
 
 fn main() -> Result<(), Error> {
     env_logger::init();
+    let cli = Cli::parse();
 
-    let config = config::parsing::read_config_file(Path::new("config.toml"))?;
-    let keyboards = config
-        .devices
-        .extract_devices_to_remap(get_all_devices()?)?;
-
-    println!("Selected devices:");
-    print_devices(&keyboards);
-
-    // println!();
-    // let virtual_keyboard_name = "My Virtual Keyboard";
-    // let virtual_device =
-    //     VirtualDevice::from_template_device(virtual_keyboard_name, &mut chosen_keyboard)?;
-    // println!("Virtual keyboard {virtual_keyboard_name}");
-    // println!();
-
-    // let (keyboards, _) = get_all_keyboards();
-    // println!("New keyboards found:");
-    // print_devices(&keyboards);
-    return Ok(());
+    match cli.command {
+        Some(Command::CheckConfig) => commands::check_config::check_config(&cli.config),
+        Some(Command::Doctor) => commands::doctor::doctor(),
+        Some(Command::ListDevices { json }) => {
+            commands::list_devices::list_devices(&cli.config, json)
+        }
+        Some(Command::Record {
+            output,
+            grab,
+            selection,
+        }) => commands::record::record(&cli.config, &output, grab, selection.explain),
+        Some(Command::Replay { trace, expect }) => {
+            commands::replay::replay(&cli.config, &trace, expect.as_deref())
+        }
+        None => remap(&cli.config, cli.selection.explain),
+    }
+}
+
+fn remap(config_path: &Path, explain: bool) -> Result<(), Error> {
+    let config = config::parsing::read_config_file(config_path)?;
+    logging::set_log_keys(config.log_keys);
+    let mut engine = Engine::new(&config);
+    let privileges = config.privileges.clone().unwrap_or_default();
+    // Started before any device is opened, so the helper holds none of them open.
+    let opener = match privileges::will_drop_privileges(&privileges) {
+        true => Some(DeviceOpener::spawn()?),
+        false => None,
+    };
+    let keyboards = config.devices.select_devices(get_all_devices()?, explain)?;
+
+    println!("Selected devices, run `chorded-key-remapper list-devices` to see why:");
+    for keyboard in &keyboards {
+        let path = keyboard.path().unwrap_or(Path::new("?"));
+        println!("'{}' at {}", keyboard, path.display());
+    }
+
+    let mut source = EvdevSource::new(keyboards)?;
+    if let Some(opener) = opener {
+        source.reopen_through(opener);
+    }
+    // Declared after the source so it's dropped first, releasing any keys held down before the
+    // devices are ungrabbed, however remapping stops.
+    let mut sink = ReleasingSink::new(EvdevSink(VirtualDevice::with_all_keys(
+        VIRTUAL_KEYBOARD_NAME,
+    )?));
+    source.watch_config(config_path)?;
+    source.grab()?;
+    // Everything needing root is open by now.
+    privileges::drop_privileges(&privileges)?;
+    if privileges.seccomp {
+        privileges::restrict_syscalls()?;
+    }
+    loop {
+        match remapper::run(&mut engine, &mut source, &mut sink)? {
+            Stop::ConfigChanged => {}
+            Stop::Closed => break,
+            Stop::Escaped => {
+                // The keys are released and the devices ungrabbed as the sink and source drop.
+                println!("Escape chord pressed, stopping.");
+                break;
+            }
+        }
+        match config::parsing::read_config_file(config_path) {
+            Ok(config) => {
+                log::info!("Reloaded config {:?}", config_path);
+                logging::set_log_keys(config.log_keys);
+                // The keys held down are remapped afresh by the new config.
+                engine.reload(&config);
+                source.request_resync();
+            }
+            Err(err) => log::error!(
+                "Keeping the previous config, as the new one is invalid: {}",
+                err
+            ),
+        }
+    }
+    sink.release_all()?;
+    Ok(())
 }
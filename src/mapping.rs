@@ -1,8 +1,64 @@
+use crate::key::{KeySet, KEY_CODE_COUNT};
+use crate::position::{Finger, Hand};
 use crate::Key;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Deserialize, Debug)]
+// Deserialize is implemented in config::deserialize, as keys can be referred to by aliases
+// which are only known once the config file is being read.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Map {
     pub input: Vec<Key>,
+    pub kind: InputKind,
     pub output: Vec<Key>,
+    /// The keys which the config gave by finger, so they're written back out the same way.
+    pub fingers: Vec<FingerKey>,
+}
+
+/// A chord can't have more keys than there are fingers to press them with. Every part of a chord
+/// is indexed while it's pressed, so the limit also keeps the index small.
+pub const MAX_CHORD_KEYS: usize = 10;
+
+impl Map {
+    /// Check the map can be played, describing the problem if not.
+    pub fn check(&self) -> Result<(), String> {
+        if self.kind != InputKind::Chord {
+            return Ok(());
+        }
+        if let Some(key) = self.input.iter().find(|key| key.code() >= KEY_CODE_COUNT) {
+            return Err(format!(
+                "has key code {}, which is out of range",
+                key.code()
+            ));
+        }
+        if KeySet::from_iter(self.input.iter().copied()).len() != self.input.len() {
+            return Err("has the same key more than once".to_owned());
+        }
+        if self.input.len() > MAX_CHORD_KEYS {
+            return Err(format!(
+                "has {} keys, but a chord can have at most {}",
+                self.input.len(),
+                MAX_CHORD_KEYS
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A key given in the config by the finger it's assigned to, e.g. `left.index`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FingerKey {
+    pub key: Key,
+    pub hand: Hand,
+    pub finger: Finger,
+}
+
+/// How the keys of a map's input have to be pressed for it to trigger.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InputKind {
+    /// All keys held down together, in any order.
+    #[default]
+    Chord,
+    /// Keys pressed one after another.
+    Sequence,
 }
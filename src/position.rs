@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Key;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Hand {
+    Left,
+    Right,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Finger {
+    Thumb,
+    Index,
+    Middle,
+    Ring,
+    #[serde(alias = "pinky")]
+    Little,
+}
+
+impl Finger {
+    pub const ALL: [Finger; 5] = [
+        Finger::Thumb,
+        Finger::Index,
+        Finger::Middle,
+        Finger::Ring,
+        Finger::Little,
+    ];
+}
+
+/// The keys each finger covers, as given by the `[position.left_hand]` and
+/// `[position.right_hand]` tables of the config. The first key of each finger is the one it
+/// rests on.
+///
+/// Optionally the rows of the keyboard, each listed from left to right, as given by
+/// `position.rows`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Positions {
+    fingers: BTreeMap<(Hand, Finger), Vec<Key>>,
+    rows: Vec<Vec<Key>>,
+}
+
+impl Positions {
+    /// Fails with the key and its fingers if a key is assigned to more than one finger.
+    pub fn new(
+        fingers: BTreeMap<(Hand, Finger), Vec<Key>>,
+    ) -> Result<Positions, (Key, Vec<String>)> {
+        let positions = Positions {
+            fingers: fingers
+                .into_iter()
+                .filter(|(_, keys)| !keys.is_empty())
+                .collect(),
+            rows: Vec::new(),
+        };
+        for (_, _, key) in positions.iter() {
+            let assigned: Vec<String> = positions
+                .fingers
+                .iter()
+                .filter(|(_, keys)| keys.contains(&key))
+                .map(|((hand, finger), _)| format_finger(*hand, *finger))
+                .collect();
+            if assigned.len() > 1 {
+                return Err((key, assigned));
+            }
+        }
+        Ok(positions)
+    }
+
+    pub fn with_rows(self, rows: Vec<Vec<Key>>) -> Positions {
+        Positions { rows, ..self }
+    }
+
+    pub fn rows(&self) -> &[Vec<Key>] {
+        &self.rows
+    }
+
+    /// The row and column of `key` in `rows`.
+    fn row_and_column(&self, key: Key) -> Option<(usize, usize)> {
+        self.rows.iter().enumerate().find_map(|(row, keys)| {
+            keys.iter()
+                .position(|other| *other == key)
+                .map(|column| (row, column))
+        })
+    }
+
+    /// Keys pressed by the same finger in the same row of opposite hands, in both directions,
+    /// e.g. `(KEY_F, KEY_J)` and `(KEY_J, KEY_F)`.
+    ///
+    /// Where a finger covers more than one key of a row, its keys are paired up starting from the
+    /// centre of the keyboard. Keys in no row are paired in the order they're listed for the
+    /// finger.
+    pub fn mirror_pairs(&self) -> Vec<(Key, Key)> {
+        let mut pairs = Vec::new();
+        for finger in Finger::ALL {
+            let left = self.keys(Hand::Left, finger);
+            let right = self.keys(Hand::Right, finger);
+
+            let mut rows: Vec<Option<usize>> = Vec::new();
+            for key in left.iter().chain(right) {
+                let row = self.row_and_column(*key).map(|(row, _)| row);
+                if !rows.contains(&row) {
+                    rows.push(row);
+                }
+            }
+
+            for row in rows {
+                let in_row = |keys: &[Key]| -> Vec<(usize, Key)> {
+                    keys.iter()
+                        .map(|key| (self.row_and_column(*key), *key))
+                        .filter(|(position, _)| position.map(|(row, _)| row) == row)
+                        .map(|(position, key)| (position.map_or(0, |(_, column)| column), key))
+                        .collect()
+                };
+                let mut left_keys = in_row(left);
+                let mut right_keys = in_row(right);
+                // Innermost keys first, the left hand's are on the right of its rows.
+                if row.is_some() {
+                    left_keys.sort_by_key(|(column, _)| std::cmp::Reverse(*column));
+                    right_keys.sort_by_key(|(column, _)| *column);
+                }
+                for ((_, left_key), (_, right_key)) in left_keys.into_iter().zip(right_keys) {
+                    pairs.push((left_key, right_key));
+                    pairs.push((right_key, left_key));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// The key `finger` rests on.
+    pub fn key(&self, hand: Hand, finger: Finger) -> Option<Key> {
+        self.keys(hand, finger).first().copied()
+    }
+
+    /// Every key `finger` covers, starting with the one it rests on.
+    pub fn keys(&self, hand: Hand, finger: Finger) -> &[Key] {
+        self.fingers
+            .get(&(hand, finger))
+            .map_or(&[], |keys| keys.as_slice())
+    }
+
+    pub fn finger_of(&self, key: Key) -> Option<(Hand, Finger)> {
+        self.iter()
+            .find(|(_, _, other)| *other == key)
+            .map(|(hand, finger, _)| (hand, finger))
+    }
+
+    pub fn hand_of(&self, key: Key) -> Option<Hand> {
+        self.finger_of(key).map(|(hand, _)| hand)
+    }
+
+    /// Whether `key` is the one its finger rests on.
+    pub fn is_resting_key(&self, key: Key) -> bool {
+        self.finger_of(key)
+            .is_some_and(|(hand, finger)| self.key(hand, finger) == Some(key))
+    }
+
+    /// Every finger which covers any keys, with the keys it covers.
+    pub fn fingers(&self) -> impl Iterator<Item = (Hand, Finger, &[Key])> + '_ {
+        self.fingers
+            .iter()
+            .map(|((hand, finger), keys)| (*hand, *finger, keys.as_slice()))
+    }
+
+    /// Every covered key, with the finger which covers it.
+    pub fn iter(&self) -> impl Iterator<Item = (Hand, Finger, Key)> + '_ {
+        self.fingers
+            .iter()
+            .flat_map(|((hand, finger), keys)| keys.iter().map(move |key| (*hand, *finger, *key)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fingers.is_empty() && self.rows.is_empty()
+    }
+}
+
+/// A finger as referred to in place of a key in the config, e.g. `left.index`.
+pub fn format_finger(hand: Hand, finger: Finger) -> String {
+    format!("{}.{}", hand, finger)
+}
+
+/// Parse a finger reference such as `left.index`, `None` if `name` isn't one.
+pub fn parse_finger(name: &str) -> Option<(Hand, Finger)> {
+    let (hand, finger) = name.trim().split_once('.')?;
+    Some((hand.parse().ok()?, finger.parse().ok()?))
+}
+
+impl fmt::Display for Hand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Hand::Left => "left",
+            Hand::Right => "right",
+        })
+    }
+}
+
+impl FromStr for Hand {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "left" => Ok(Hand::Left),
+            "right" => Ok(Hand::Right),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Finger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Finger::Thumb => "thumb",
+            Finger::Index => "index",
+            Finger::Middle => "middle",
+            Finger::Ring => "ring",
+            Finger::Little => "little",
+        })
+    }
+}
+
+impl FromStr for Finger {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "thumb" => Ok(Finger::Thumb),
+            "index" => Ok(Finger::Index),
+            "middle" => Ok(Finger::Middle),
+            "ring" => Ok(Finger::Ring),
+            "little" | "pinky" => Ok(Finger::Little),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_mirror_pairs {
+    use super::*;
+
+    fn positions() -> Positions {
+        Positions::new(BTreeMap::from([
+            ((Hand::Left, Finger::Thumb), vec![Key::KEY_SPACE]),
+            (
+                (Hand::Left, Finger::Index),
+                vec![Key::KEY_F, Key::KEY_G, Key::KEY_R],
+            ),
+            ((Hand::Left, Finger::Middle), vec![Key::KEY_D]),
+            ((Hand::Right, Finger::Thumb), vec![Key::KEY_RIGHTALT]),
+            (
+                (Hand::Right, Finger::Index),
+                vec![Key::KEY_J, Key::KEY_H, Key::KEY_U],
+            ),
+            ((Hand::Right, Finger::Middle), vec![Key::KEY_K, Key::KEY_I]),
+        ]))
+        .unwrap()
+        .with_rows(vec![
+            vec![Key::KEY_R, Key::KEY_T, Key::KEY_Y, Key::KEY_U, Key::KEY_I],
+            vec![
+                Key::KEY_D,
+                Key::KEY_F,
+                Key::KEY_G,
+                Key::KEY_H,
+                Key::KEY_J,
+                Key::KEY_K,
+            ],
+        ])
+    }
+
+    fn mirror(key: Key) -> Option<Key> {
+        positions()
+            .mirror_pairs()
+            .into_iter()
+            .find(|(from, _)| *from == key)
+            .map(|(_, to)| to)
+    }
+
+    #[test]
+    fn keys_are_mirrored_finger_for_finger_in_the_same_row() {
+        assert_eq!(mirror(Key::KEY_F), Some(Key::KEY_J));
+        assert_eq!(mirror(Key::KEY_J), Some(Key::KEY_F));
+        assert_eq!(mirror(Key::KEY_D), Some(Key::KEY_K));
+        assert_eq!(mirror(Key::KEY_R), Some(Key::KEY_U));
+    }
+
+    #[test]
+    fn keys_of_one_finger_in_one_row_are_paired_from_the_centre() {
+        assert_eq!(mirror(Key::KEY_G), Some(Key::KEY_H));
+    }
+
+    #[test]
+    fn keys_in_no_row_are_paired_in_order() {
+        assert_eq!(mirror(Key::KEY_SPACE), Some(Key::KEY_RIGHTALT));
+    }
+
+    #[test]
+    fn keys_without_a_counterpart_are_not_mirrored() {
+        assert_eq!(mirror(Key::KEY_I), None);
+    }
+}
@@ -0,0 +1,257 @@
+// Giving up root once the devices are open. The grabbed devices and the virtual device stay
+// usable through their open file descriptors, so the event loop needs no privileges, except to
+// reopen devices which are unplugged and plugged back in. Those are opened by a helper process
+// which keeps root, see `device::opener`.
+
+use std::ffi::CString;
+
+use nix::unistd::{getuid, initgroups, setgid, setuid, Gid, Group, Uid, User};
+
+use crate::config::schema::PrivilegesConfig;
+use crate::errors::Error;
+
+/// Whether `drop_privileges` will give up root with `config`.
+pub fn will_drop_privileges(config: &PrivilegesConfig) -> bool {
+    config.user.is_some() && getuid().is_root()
+}
+
+/// Switch to the user and group in `config`, if running as root. Does nothing when no user is
+/// configured.
+pub fn drop_privileges(config: &PrivilegesConfig) -> Result<(), Error> {
+    let user_name = match &config.user {
+        Some(user_name) => user_name,
+        None => return Ok(()),
+    };
+    if !getuid().is_root() {
+        log::info!(
+            "Not running as root, so not switching to user {}",
+            user_name
+        );
+        return Ok(());
+    }
+    let user = User::from_name(user_name)
+        .map_err(std::io::Error::from)?
+        .ok_or_else(|| Error::Message(format!("No user called {}", user_name)))?;
+    let gid = match &config.group {
+        None => user.gid,
+        Some(group_name) => {
+            Group::from_name(group_name)
+                .map_err(std::io::Error::from)?
+                .ok_or_else(|| Error::Message(format!("No group called {}", group_name)))?
+                .gid
+        }
+    };
+
+    // Groups first, as changing them needs root.
+    let name = CString::new(user.name.as_str())
+        .map_err(|_| Error::Message(format!("Invalid user name {:?}", user.name)))?;
+    initgroups(&name, gid).map_err(std::io::Error::from)?;
+    setgid(gid).map_err(std::io::Error::from)?;
+    setuid(user.uid).map_err(std::io::Error::from)?;
+
+    if setuid(Uid::from_raw(0)).is_ok() || setgid(Gid::from_raw(0)).is_ok() {
+        return Err(Error::Message(
+            "Root privileges could be regained".to_owned(),
+        ));
+    }
+    println!("Running as user {}", user.name);
+    Ok(())
+}
+
+/// Whether the system calls can be restricted with seccomp on this architecture.
+pub const SECCOMP_SUPPORTED: bool = cfg!(any(target_arch = "x86_64", target_arch = "aarch64"));
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::seccomp::restrict_syscalls;
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub fn restrict_syscalls() -> Result<(), Error> {
+    Err(Error::Message(
+        "seccomp is unsupported on this architecture".to_owned(),
+    ))
+}
+
+// The filter checks the architecture of each system call, and system call numbers differ
+// between architectures, so it's only built for those listed.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod seccomp {
+    use nix::libc::{self, sock_filter, sock_fprog};
+
+    use crate::errors::Error;
+
+    /// The system calls the event loop makes, including reading the config, logging and reopening
+    /// devices, directly or through the helper.
+    fn allowed_syscalls() -> Vec<libc::c_long> {
+        let mut syscalls = vec![
+            libc::SYS_read,
+            libc::SYS_write,
+            libc::SYS_writev,
+            libc::SYS_openat,
+            libc::SYS_close,
+            libc::SYS_ioctl,
+            libc::SYS_lseek,
+            libc::SYS_fcntl,
+            libc::SYS_fstat,
+            libc::SYS_newfstatat,
+            libc::SYS_statx,
+            libc::SYS_getdents64,
+            libc::SYS_sendmsg,
+            libc::SYS_recvmsg,
+            libc::SYS_epoll_pwait,
+            libc::SYS_epoll_ctl,
+            libc::SYS_timerfd_settime,
+            libc::SYS_clock_gettime,
+            libc::SYS_clock_nanosleep,
+            libc::SYS_nanosleep,
+            libc::SYS_mmap,
+            libc::SYS_munmap,
+            libc::SYS_mremap,
+            libc::SYS_brk,
+            libc::SYS_madvise,
+            libc::SYS_futex,
+            libc::SYS_sched_yield,
+            libc::SYS_getrandom,
+            libc::SYS_rt_sigreturn,
+            libc::SYS_rt_sigprocmask,
+            libc::SYS_sigaltstack,
+            libc::SYS_exit,
+            libc::SYS_exit_group,
+        ];
+        #[cfg(target_arch = "x86_64")]
+        syscalls.extend([
+            libc::SYS_open,
+            libc::SYS_stat,
+            libc::SYS_poll,
+            libc::SYS_epoll_wait,
+        ]);
+        syscalls
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    // Classic BPF instructions, from linux/filter.h.
+    /// BPF_LD | BPF_W | BPF_ABS
+    const BPF_LD_W_ABS: u16 = 0x20;
+    /// BPF_JMP | BPF_JEQ | BPF_K
+    const BPF_JEQ_K: u16 = 0x15;
+    /// BPF_RET | BPF_K
+    const BPF_RET_K: u16 = 0x06;
+    // Offsets into struct seccomp_data.
+    const SYSCALL_NR_OFFSET: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+
+    fn instruction(code: u16, jt: u8, jf: u8, k: u32) -> sock_filter {
+        sock_filter { code, jt, jf, k }
+    }
+
+    /// A seccomp filter allowing only `syscalls`. Any other fails with EPERM, showing up as an
+    /// error rather than killing the remapper, and system calls of any other architecture kill
+    /// it.
+    fn seccomp_filter(syscalls: &[libc::c_long]) -> Vec<sock_filter> {
+        let mut filter = vec![
+            instruction(BPF_LD_W_ABS, 0, 0, ARCH_OFFSET),
+            instruction(BPF_JEQ_K, 1, 0, AUDIT_ARCH),
+            instruction(BPF_RET_K, 0, 0, libc::SECCOMP_RET_KILL_PROCESS),
+            instruction(BPF_LD_W_ABS, 0, 0, SYSCALL_NR_OFFSET),
+        ];
+        for syscall in syscalls {
+            filter.push(instruction(BPF_JEQ_K, 0, 1, *syscall as u32));
+            filter.push(instruction(BPF_RET_K, 0, 0, libc::SECCOMP_RET_ALLOW));
+        }
+        filter.push(instruction(
+            BPF_RET_K,
+            0,
+            0,
+            libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+        ));
+        filter
+    }
+
+    /// Install `filter` for the rest of the process's life.
+    fn install_filter(filter: &mut [sock_filter]) -> std::io::Result<()> {
+        let program = sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+        unsafe {
+            // Needed to install a filter without root, and so nothing run later can gain
+            // privileges.
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                || libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &program as *const sock_fprog,
+                ) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Allow only the system calls the event loop needs, for the rest of the process's life.
+    pub fn restrict_syscalls() -> Result<(), Error> {
+        install_filter(&mut seccomp_filter(&allowed_syscalls()))?;
+        log::info!("Restricted system calls with seccomp");
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod test_seccomp_filter {
+        use super::*;
+        use nix::sys::wait::{waitpid, WaitStatus};
+        use nix::unistd::{fork, ForkResult};
+
+        #[test]
+        fn other_architectures_are_killed() {
+            let filter = seccomp_filter(&[libc::SYS_read]);
+            assert_eq!(filter[1].k, AUDIT_ARCH);
+            assert_eq!(filter[2].k, libc::SECCOMP_RET_KILL_PROCESS);
+        }
+
+        #[test]
+        fn each_syscall_is_allowed_and_the_rest_fail() {
+            let filter = seccomp_filter(&[libc::SYS_read, libc::SYS_write]);
+            assert_eq!(filter.len(), 4 + 2 * 2 + 1);
+            assert_eq!(filter[4].k, libc::SYS_read as u32);
+            assert_eq!(filter[5].k, libc::SECCOMP_RET_ALLOW);
+            assert_eq!(filter[6].k, libc::SYS_write as u32);
+            assert_eq!(
+                filter.last().unwrap().k,
+                libc::SECCOMP_RET_ERRNO | libc::EPERM as u32
+            );
+        }
+
+        #[test]
+        fn installed_filter_fails_the_syscalls_not_allowed() {
+            // Built before forking, as the child of a process with other threads mustn't allocate.
+            let mut filter =
+                seccomp_filter(&[libc::SYS_getppid, libc::SYS_exit, libc::SYS_exit_group]);
+            match unsafe { fork() }.unwrap() {
+                ForkResult::Child => {
+                    let code = match install_filter(&mut filter) {
+                        Err(_) => 1,
+                        Ok(()) => unsafe {
+                            if libc::syscall(libc::SYS_getppid) <= 0 {
+                                2
+                            } else if libc::syscall(libc::SYS_getuid) != -1
+                                || *libc::__errno_location() != libc::EPERM
+                            {
+                                3
+                            } else {
+                                0
+                            }
+                        },
+                    };
+                    unsafe { libc::_exit(code) }
+                }
+                ForkResult::Parent { child } => {
+                    assert_eq!(waitpid(child, None).unwrap(), WaitStatus::Exited(child, 0));
+                }
+            }
+        }
+    }
+}
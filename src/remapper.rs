@@ -0,0 +1,143 @@
+// The remapping loop, feeding events from a source through the engine into a sink.
+
+use crate::clock::Clock;
+use crate::device::events::{EventSink, EventSource, Poll};
+use crate::engine::Engine;
+use crate::errors::DeviceError;
+use crate::logging::Events;
+
+/// Why `run` returned.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Stop {
+    Closed,
+    /// The config changed, so the engine should be rebuilt and `run` called again.
+    ConfigChanged,
+    /// The escape chord was pressed, so remapping should stop at once.
+    Escaped,
+}
+
+/// Remap events from `source` into `sink` until the source is closed, the config changes or the
+/// escape chord is pressed, handling the engine's timers as they expire.
+pub fn run<C: Clock>(
+    engine: &mut Engine<C>,
+    source: &mut impl EventSource,
+    sink: &mut impl EventSink,
+) -> Result<Stop, DeviceError> {
+    loop {
+        let output = match source.poll(engine.next_deadline())? {
+            Poll::Event(event) if engine.escape_pressed(&event) => return Ok(Stop::Escaped),
+            Poll::Event(event) => {
+                let output = engine.process(event);
+                log::debug!("{} in, {} out", Events(&[event]), Events(&output));
+                output
+            }
+            Poll::Timeout(_) => engine.timeout(),
+            Poll::Resync { held, .. } => engine.resync(&held),
+            Poll::ConfigChanged => return Ok(Stop::ConfigChanged),
+            Poll::Closed => return Ok(Stop::Closed),
+        };
+        if !output.is_empty() {
+            sink.emit(&output)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_run {
+    use super::*;
+    use crate::device::events::{MemorySink, ScriptedSource};
+    use crate::engine::test_utils::{engine, engine_on, press, release};
+    use crate::key::KeyEvent;
+    use crate::Key;
+    use std::time::Duration;
+
+    #[test]
+    fn scripted_events_are_remapped_in_virtual_time() {
+        let mut source = ScriptedSource::new([
+            press(Key::KEY_S, 0),
+            press(Key::KEY_D, 20),
+            release(Key::KEY_S, 100),
+            release(Key::KEY_D, 110),
+            press(Key::KEY_S, 1000),
+            release(Key::KEY_S, 1200),
+        ]);
+        let mut engine = engine_on(
+            r#"
+[mappings.maps]
+"s+d" = "up"
+"#,
+            source.clock(),
+        );
+        let mut sink = MemorySink::default();
+        run(&mut engine, &mut source, &mut sink).unwrap();
+        assert_eq!(
+            sink.events,
+            vec![
+                press(Key::KEY_UP, 20),
+                release(Key::KEY_UP, 100),
+                // The lone key is let through once the chord timeout expires, with its own time.
+                press(Key::KEY_S, 1000),
+                release(Key::KEY_S, 1200),
+            ]
+        );
+    }
+
+    /// Returns every event at once, however long ago it happened, like a source read late
+    /// because the machine was busy.
+    struct BurstSource(Vec<KeyEvent>);
+
+    impl EventSource for BurstSource {
+        fn poll(&mut self, _deadline: Option<Duration>) -> Result<Poll, DeviceError> {
+            match self.0.is_empty() {
+                true => Ok(Poll::Closed),
+                false => Ok(Poll::Event(self.0.remove(0))),
+            }
+        }
+    }
+
+    #[test]
+    fn events_read_late_are_timed_by_when_they_happened() {
+        let mut engine = engine(
+            r#"
+[mappings.maps]
+"s+d" = "up"
+"#,
+        );
+        let mut source = BurstSource(vec![
+            press(Key::KEY_S, 0),
+            press(Key::KEY_D, 20),
+            release(Key::KEY_S, 100),
+            release(Key::KEY_D, 110),
+        ]);
+        let mut sink = MemorySink::default();
+        run(&mut engine, &mut source, &mut sink).unwrap();
+        assert_eq!(
+            sink.events,
+            vec![press(Key::KEY_UP, 20), release(Key::KEY_UP, 100)]
+        );
+    }
+
+    #[test]
+    fn escape_chord_stops_remapping_before_any_map_sees_it() {
+        let mut source = ScriptedSource::new([
+            press(Key::KEY_J, 0),
+            press(Key::KEY_K, 10),
+            release(Key::KEY_K, 100),
+        ]);
+        let mut engine = engine_on(
+            r#"
+escape_chord = "j+k"
+
+[mappings.maps]
+"j+k" = "esc"
+"#,
+            source.clock(),
+        );
+        let mut sink = MemorySink::default();
+        assert_eq!(
+            run(&mut engine, &mut source, &mut sink).unwrap(),
+            Stop::Escaped
+        );
+        assert_eq!(sink.events, vec![]);
+    }
+}
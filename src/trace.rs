@@ -0,0 +1,196 @@
+// A plain-text format for key events, one event per line, for reproducing bug reports and
+// writing regression tests:
+//
+//   # Comments and blank lines are ignored.
+//   +KEY_S @0ms
+//   +KEY_D @12ms
+//   ~KEY_D @300ms
+//   -KEY_S @380.5ms
+//
+// "+" is a press, "-" a release and "~" a repeat. Keys are given by name, or by key code for
+// keys without one. Times are in milliseconds from an arbitrary origin, and can't go backwards.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::errors::TraceError;
+use crate::key::{KeyEvent, KeyState, KEY_CODE_COUNT};
+use crate::Key;
+
+pub fn parse_trace(content: &str) -> Result<Vec<KeyEvent>, TraceError> {
+    let mut events: Vec<KeyEvent> = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| TraceError {
+            line: index + 1,
+            message,
+        };
+        let event = parse_event(line).map_err(error)?;
+        if let Some(previous) = events.last() {
+            if event.time < previous.time {
+                return Err(error(format!(
+                    "Time goes backwards, from {}",
+                    format_time(previous.time)
+                )));
+            }
+        }
+        events.push(event);
+    }
+    Ok(events)
+}
+
+fn parse_event(line: &str) -> Result<KeyEvent, String> {
+    let (key, time) = match line.split_once('@') {
+        Some((key, time)) => (key.trim(), time.trim()),
+        None => {
+            return Err(format!(
+                "Expected an event like \"+KEY_S @0ms\", got {:?}",
+                line
+            ))
+        }
+    };
+
+    let mut chars = key.chars();
+    let state = match chars.next() {
+        Some('+') => KeyState::Pressed,
+        Some('-') => KeyState::Released,
+        Some('~') => KeyState::Repeated,
+        _ => {
+            return Err(format!(
+                "Expected \"+\", \"-\" or \"~\" before the key, got {:?}",
+                key
+            ))
+        }
+    };
+    Ok(KeyEvent::new(
+        parse_key(chars.as_str())?,
+        state,
+        parse_time(time)?,
+    ))
+}
+
+fn parse_key(name: &str) -> Result<Key, String> {
+    if let Ok(key) = Key::from_str(name) {
+        return Ok(key);
+    }
+    match name.parse::<u16>() {
+        Ok(code) if code < KEY_CODE_COUNT => Ok(Key::new(code)),
+        _ => Err(format!("Unrecognised key: {:?}", name)),
+    }
+}
+
+fn parse_time(time: &str) -> Result<Duration, String> {
+    let error = || format!("Expected a time like \"@12ms\", got {:?}", time);
+    let milliseconds = time.strip_suffix("ms").ok_or_else(error)?;
+    let (whole, fraction) = milliseconds.split_once('.').unwrap_or((milliseconds, ""));
+    let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if whole.is_empty() || !digits(whole) || !digits(fraction) || fraction.len() > 6 {
+        return Err(error());
+    }
+    let whole: u64 = whole.parse().map_err(|_| error())?;
+    let nanos: u64 = format!("{:0<6}", fraction).parse().map_err(|_| error())?;
+    Ok(Duration::from_millis(whole) + Duration::from_nanos(nanos))
+}
+
+fn format_key(key: Key) -> String {
+    let name = format!("{:?}", key);
+    match Key::from_str(&name) {
+        Ok(_) => name,
+        Err(_) => key.code().to_string(),
+    }
+}
+
+/// Milliseconds, with as many decimal places as needed for microseconds.
+fn format_time(time: Duration) -> String {
+    let micros = time.as_micros();
+    match micros % 1000 {
+        0 => format!("@{}ms", micros / 1000),
+        fraction => {
+            let formatted = format!("@{}.{:03}", micros / 1000, fraction);
+            format!("{}ms", formatted.trim_end_matches('0'))
+        }
+    }
+}
+
+pub fn format_event(event: &KeyEvent) -> String {
+    let state = match event.state {
+        KeyState::Pressed => '+',
+        KeyState::Released => '-',
+        KeyState::Repeated => '~',
+    };
+    format!(
+        "{}{} {}",
+        state,
+        format_key(event.key),
+        format_time(event.time)
+    )
+}
+
+pub fn format_trace(events: &[KeyEvent]) -> String {
+    events
+        .iter()
+        .map(|event| format_event(event) + "\n")
+        .collect()
+}
+
+#[cfg(test)]
+mod test_trace {
+    use super::*;
+
+    fn event(key: Key, state: KeyState, micros: u64) -> KeyEvent {
+        KeyEvent::new(key, state, Duration::from_micros(micros))
+    }
+
+    #[test]
+    fn events_are_parsed() {
+        let trace = "
+# A chord
++KEY_S @0ms
++KEY_D @12ms
+~KEY_D @300ms
+-KEY_S @380.5ms
+-700 @400ms
+";
+        assert_eq!(
+            parse_trace(trace).unwrap(),
+            vec![
+                event(Key::KEY_S, KeyState::Pressed, 0),
+                event(Key::KEY_D, KeyState::Pressed, 12_000),
+                event(Key::KEY_D, KeyState::Repeated, 300_000),
+                event(Key::KEY_S, KeyState::Released, 380_500),
+                event(Key::new(700), KeyState::Released, 400_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn events_round_trip() {
+        let events = vec![
+            event(Key::KEY_S, KeyState::Pressed, 0),
+            event(Key::KEY_D, KeyState::Repeated, 12_345),
+            event(Key::new(700), KeyState::Released, 80_000),
+        ];
+        let trace = format_trace(&events);
+        assert_eq!(trace, "+KEY_S @0ms\n~KEY_D @12.345ms\n-700 @80ms\n");
+        assert_eq!(parse_trace(&trace).unwrap(), events);
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        let error = parse_trace("+KEY_S @0ms\n\n+KEY_NOT_A_KEY @1ms").unwrap_err();
+        assert_eq!(error.line, 3);
+    }
+
+    #[test]
+    fn time_cannot_go_backwards() {
+        assert!(parse_trace("+KEY_S @10ms\n-KEY_S @5ms").is_err());
+    }
+
+    #[test]
+    fn time_needs_units() {
+        assert!(parse_trace("+KEY_S @10").is_err());
+    }
+}